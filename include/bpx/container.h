@@ -30,6 +30,7 @@
 #define BPX_CONTAINER_H
 
 #include "bpx/types.h"
+#include "bpx/open2.h"
 
 #include <stdbool.h>
 
@@ -47,15 +48,49 @@ typedef struct bpx_section_options_s
 #define BPX_CHECKSUM_WEAK 0x4
 #define BPX_CHECKSUM_CRC32 0x8
 
-void bpx_container_get_main_header(bpx_container_t container, bpx_main_header_t *main_header);
-void bpx_container_list_sections(bpx_container_t container, bpx_handle_t *out, size_t size);
+#define BPX_SECTION_TYPE_ANY -1
+
+typedef struct bpx_section_filter_s
+{
+    bool by_type;
+    bpx_u8_t ty;
+    //Only sections with (flags & flags_mask) == flags are matched
+    bpx_u8_t flags_mask;
+    bpx_u8_t flags;
+} bpx_section_filter_t;
+
+bpx_error_t bpx_container_get_main_header(bpx_container_t container, bpx_main_header_t *main_header);
+bpx_error_t bpx_container_list_sections(bpx_container_t container, bpx_handle_t *out, size_t size);
+bpx_size_t bpx_container_count_sections(bpx_container_t container, int ty); //ty may be BPX_SECTION_TYPE_ANY
+
+/* Section iteration, the iterator is a snapshot of the matching sections (including unsaved ones) taken at creation */
+bpx_error_t bpx_section_iter_new(bpx_container_t container, const bpx_section_filter_t *filter, bpx_section_iter_t *out); //filter may be NULL
+bpx_size_t bpx_section_iter_len(bpx_section_iter_t iter);
+bool bpx_section_iter_next(bpx_section_iter_t iter, bpx_handle_t *handle);
+bpx_error_t bpx_section_iter_reset(bpx_section_iter_t iter);
+bpx_error_t bpx_section_iter_close(bpx_section_iter_t *iter);
+
 bool bpx_container_find_section_by_type(bpx_container_t container, bpx_u8_t ty, bpx_handle_t *handle);
 bool bpx_container_find_section_by_index(bpx_container_t container, bpx_u32_t idx, bpx_handle_t *handle);
-void bpx_container_create_section(bpx_container_t container, const bpx_section_options_t *options);
-void bpx_container_remove_section(bpx_container_t container, bpx_handle_t handle);
+
+//Mutating a container while some of its sections are open fails with BPX_ERR_OPEN_SECTION_IN_USE.
+bpx_error_t bpx_container_create_section(bpx_container_t container, const bpx_section_options_t *options, bpx_handle_t *handle);
+bpx_error_t bpx_container_remove_section(bpx_container_t container, bpx_handle_t handle);
+
+//Copies a section with its type and flags into dst (which may be src), compressed sections stay compressed whatever
+//their size. The compressed payload, csize and checksum of an unmodified compressed section are reused as is instead of
+//being re-encoded, unless the copy is loaded or opened before dst is saved: it is then decompressed and the next save
+//compresses it again, as the last section of dst.
+bpx_error_t bpx_container_copy_section(bpx_container_t src, bpx_handle_t src_handle, bpx_container_t dst, bpx_handle_t *out_handle);
 
 bpx_error_t bpx_container_save(bpx_container_t container);
 
-void bpx_container_close(bpx_container_t *container);
+//Writes a copy of the container to a new destination, the container itself stays attached to its original backend.
+//The file is written to a uniquely named temporary file next to the target, synced and then renamed over it.
+bpx_error_t bpx_container_save_as(bpx_container_t container, const char *file);
+bpx_error_t bpx_container_save_to_io(bpx_container_t container, bpx_container_io_t io);
+
+//Returns BPX_ERR_OPEN_SECTION_IN_USE and leaves the container open while some of its sections are not closed.
+bpx_error_t bpx_container_close(bpx_container_t *container);
 
 #endif
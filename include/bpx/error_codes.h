@@ -33,6 +33,7 @@
 #define BPX_ERR_INVALID_PATH 0x1
 #define BPX_ERR_FILE_OPEN 0x2
 #define BPX_ERR_FILE_CREATE 0x3
+#define BPX_ERR_READ_ONLY 0x4
 
 // BPX errors
 #define BPX_ERR_CORE_CHKSUM 0x5
@@ -67,4 +68,34 @@
 #define BPX_ERR_SD_CAPACITY_EXCEEDED 0x1D
 #define BPX_ERR_SD_NOT_AN_OBJECT 0x1E
 
+// Backend errors
+#define BPX_ERR_NOT_MEMORY_BACKED 0x1F
+#define BPX_ERR_NOT_MMAP_BACKED 0x20
+#define BPX_ERR_SECTION_COMPRESSED 0x21
+
+// Section errors
+#define BPX_ERR_SECTION_IO 0x22
+#define BPX_ERR_SECTION_OUT_OF_RANGE 0x23
+
+// FFI errors
+#define BPX_ERR_PANIC 0x24 //A panic was caught, see bpx_get_last_error_message for details
+
+// Container errors
+#define BPX_ERR_INVALID_HANDLE 0x25
+
+// Verification errors
+#define BPX_ERR_SECTION_SIZE_MISMATCH 0x26
+#define BPX_ERR_SECTION_OUT_OF_BOUNDS 0x27
+#define BPX_ERR_CONTAINER_CORRUPTED 0x28
+
+// IO backend errors
+#define BPX_ERR_UNSUPPORTED_IO_VERSION 0x29
+
+// JSON errors
+#define BPX_ERR_SD_JSON_SYNTAX 0x2A
+#define BPX_ERR_SD_JSON_TYPE 0x2B
+
+// Encoding errors
+#define BPX_ERR_SD_MAX_DEPTH_EXCEEDED 0x2C
+
 #endif
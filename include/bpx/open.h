@@ -31,6 +31,8 @@
 
 #include "bpx/types.h"
 
+#include <stdbool.h>
+
 typedef struct bpx_container_options_s
 {
     bpx_u8_t ty;
@@ -38,7 +40,25 @@ typedef struct bpx_container_options_s
     bpx_u8_t type_ext[16];
 } bpx_container_options_t;
 
+typedef enum bpx_open_mode_e
+{
+    BPX_OPEN_READ_ONLY,
+    BPX_OPEN_READ_WRITE,
+    BPX_OPEN_CREATE
+} bpx_open_mode_t;
+
+typedef struct bpx_open_options_s
+{
+    bpx_open_mode_t mode;
+    //Only used by BPX_OPEN_CREATE when the file does not exist yet, may be NULL otherwise
+    const bpx_container_options_t *header;
+    //Only used by BPX_OPEN_READ_ONLY, falls back to regular reads when the file cannot be mapped. Sections are only read
+    // without copying through bpx_section_load_mapped (see bpx/section.h)
+    bool mmap;
+} bpx_open_options_t;
+
 bpx_error_t bpx_container_open(const char *file, bpx_container_t *out);
+bpx_error_t bpx_container_open_with_options(const char *file, const bpx_open_options_t *options, bpx_container_t *out);
 bpx_error_t bpx_container_create(const char *file, const bpx_container_options_t *header, bpx_container_t *out);
 
 #endif
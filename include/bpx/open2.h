@@ -51,4 +51,44 @@ typedef struct bpx_container_io_s
 bpx_error_t bpx_container_open2(bpx_container_io_t io, bpx_container_t *out);
 bpx_error_t bpx_container_create2(bpx_container_io_t io, const bpx_container_options_t *header, bpx_container_t *out);
 
+//Returns the last user defined error code returned by one of the io callbacks of the container, BPX_ERR_NONE if there
+// is none. Such codes are also returned unchanged by the function which triggered the callback.
+bpx_error_t bpx_container_get_io_error(bpx_container_t container);
+
+#define BPX_CONTAINER_IO2_VERSION 2
+
+typedef struct bpx_container_io2_s
+{
+    //Must be BPX_CONTAINER_IO2_VERSION
+    bpx_u32_t version;
+    const void *userdata;
+    //Unlike bpx_container_io_t, relative offsets (BPX_SEEK_END and BPX_SEEK_CURRENT) may be negative
+    /*@NotNull*/ bpx_error_t (*seek) (const void *userdata, bpx_seek_from_t from, bpx_i64_t offset, bpx_u64_t *new_pos);
+    /*@NotNull*/ bpx_error_t (*read) (const void *userdata, bpx_u8_t *buffer, size_t size, size_t *bytes_read);
+    bpx_error_t (*write) (const void *userdata, const bpx_u8_t *buffer, size_t size, size_t *bytes_written);
+    bpx_error_t (*flush) (const void *userdata);
+    //When set, BPX_SEEK_END seeks are resolved using the size and passed to seek as BPX_SEEK_START
+    bpx_error_t (*size) (const void *userdata, bpx_u64_t *size);
+    //Called once when the container is closed to release userdata
+    void (*close) (const void *userdata);
+    //Size of the read-ahead/write-behind buffer placed in front of the callbacks, 0 disables buffering. Buffered writes
+    // are flushed (and flush called) at the end of every successful save, whose result reports any flush error.
+    size_t buffer_size;
+} bpx_container_io2_t;
+
+typedef struct bpx_io_stats_s
+{
+    //Number of callback invocations actually performed
+    bpx_u64_t calls;
+    //Number of reads and writes served by the buffer without invoking any callback
+    bpx_u64_t saved;
+} bpx_io_stats_t;
+
+//Ownership of userdata is taken once the version check passes: close is then called even if opening fails.
+bpx_error_t bpx_container_open_io2(bpx_container_io2_t io, bpx_container_t *out);
+bpx_error_t bpx_container_create_io2(bpx_container_io2_t io, const bpx_container_options_t *header, bpx_container_t *out);
+
+//Statistics are only tracked for buffered io backends, they are all 0 otherwise
+bpx_error_t bpx_container_get_io_stats(bpx_container_t container, bpx_io_stats_t *stats);
+
 #endif
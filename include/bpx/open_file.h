@@ -0,0 +1,52 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+#ifndef BPX_OPEN_FILE_H
+#define BPX_OPEN_FILE_H
+
+#include "bpx/open.h"
+
+#include <stdbool.h>
+#include <stdio.h>
+
+//When take_ownership is true the descriptor/stream is closed together with the container, otherwise it stays open
+// and the caller must keep it alive until the container is closed.
+//Ownership is taken even if the call fails: with take_ownership true the descriptor/stream is then already closed
+// and must not be used nor closed again by the caller.
+
+#ifndef _WIN32
+//A borrowed descriptor is duplicated and therefore shares its file offset with the container.
+//The container is read-only when fd was opened with O_RDONLY.
+bpx_error_t bpx_container_open_fd(int fd, bool take_ownership, bpx_container_t *out);
+bpx_error_t bpx_container_create_fd(int fd, bool take_ownership, const bpx_container_options_t *header, bpx_container_t *out);
+#endif
+
+//All IO goes through stdio so the stream buffer and position remain consistent for the caller.
+bpx_error_t bpx_container_open_file(FILE *file, bool take_ownership, bpx_container_t *out);
+
+#endif
@@ -0,0 +1,61 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+#ifndef BPX_REPACK_H
+#define BPX_REPACK_H
+
+#include "bpx/types.h"
+#include "bpx/open2.h"
+
+#include <stdbool.h>
+
+//flags accept the BPX_COMPRESSION_* and BPX_CHECKSUM_* flags from container.h
+typedef struct bpx_repack_section_options_s
+{
+    bpx_handle_t handle;
+    bpx_u8_t flags;
+    bpx_u32_t threshold;
+} bpx_repack_section_options_t;
+
+typedef struct bpx_repack_options_s
+{
+    //Applies flags and threshold to every section not listed in sections, otherwise those sections keep their flags
+    // and compressed sections stay compressed
+    bool override_all;
+    bpx_u8_t flags;
+    bpx_u32_t threshold;
+    const bpx_repack_section_options_t *sections;
+    bpx_size_t section_count;
+} bpx_repack_options_t;
+
+//Rewrites every section into a tightly packed container written to io, options may be NULL.
+//bytes_saved is the difference between the size of the current backend and the new file size (negative if the new file
+//is larger).
+bpx_error_t bpx_container_repack(bpx_container_t container, bpx_container_io_t io, const bpx_repack_options_t *options, bpx_i64_t *bytes_saved);
+
+#endif
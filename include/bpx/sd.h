@@ -79,12 +79,71 @@ typedef struct bpx_sd_value_s {
 typedef struct bpx_sd_object_entry_s {
     bpx_u64_t hash;
     bpx_sd_value_t value;
-} bpx_sd_object_entry_t;
+    //NULL when the key name is unknown, valid as long as the entry remains in the object
+    const char *name;
+} bpx_sd_object_entry_t; //Listed in insertion order, decoded objects are listed by ascending hash
 
 bpx_error_t bpx_sd_value_decode_section(bpx_section_t section, bpx_sd_value_t *out);
 bpx_error_t bpx_sd_value_decode_memory(const bpx_u8_t *buffer, bpx_size_t size, bpx_sd_value_t *out);
 bpx_error_t bpx_sd_value_encode(bpx_section_t section, const bpx_sd_value_t *value);
 
+typedef bpx_buffer_t bpx_sd_buffer_t;
+
+//The buffer is owned by the library, free it with bpx_buffer_free (see bpx/utils.h).
+bpx_error_t bpx_sd_value_encode_memory(const bpx_sd_value_t *value, bpx_sd_buffer_t *out);
+//Size in bytes of the encoded value, without allocating it.
+bpx_error_t bpx_sd_value_encoded_size(const bpx_sd_value_t *value, bpx_size_t *size);
+
+typedef struct bpx_sd_encode_options_s
+{
+    //Stores the names of keys set through bpx_sd_object_set in the "__debug__" array of their object, decoding loads
+    // them back as entry names. The array takes one of the 255 entries of an object, objects with 255 entries are
+    // written without it
+    bool debug_symbols;
+    //Writes object keys (and debug symbols) sorted by hash so that equal values always encode to the same bytes,
+    // otherwise keys are written in insertion order
+    bool canonical;
+} bpx_sd_encode_options_t;
+
+//Same as above with options, which may be NULL. Values with more than 255 levels of nested arrays and objects (the
+//root object included) fail with BPX_ERR_SD_MAX_DEPTH_EXCEEDED.
+bpx_error_t bpx_sd_value_encode2(bpx_section_t section, const bpx_sd_value_t *value, const bpx_sd_encode_options_t *options);
+bpx_error_t bpx_sd_value_encode_memory2(const bpx_sd_value_t *value, const bpx_sd_encode_options_t *options, bpx_sd_buffer_t *out);
+bpx_error_t bpx_sd_value_encoded_size2(const bpx_sd_value_t *value, const bpx_sd_encode_options_t *options, bpx_size_t *size);
+
+typedef struct bpx_sd_json_options_s
+{
+    //Numbers are written as {"$u16": 5} so that bpx_sd_value_from_json restores their exact type
+    bool tagged;
+    //Optional table of key names, keys not found in it are written as hex hashes ("0x%016x")
+    const char *const *symbols;
+    bpx_size_t symbol_count;
+} bpx_sd_json_options_t;
+
+//options may be NULL. The returned string must be freed with bpx_string_free (see bpx/utils.h). Object keys are written
+// in insertion order, values nested deeper than 255 levels fail with BPX_ERR_SD_MAX_DEPTH_EXCEEDED.
+bpx_error_t bpx_sd_value_to_json(const bpx_sd_value_t *value, const bpx_sd_json_options_t *options, char **out);
+//Tagged numbers are restored with their exact type, other integers become int64 (uint64 above its range) and other
+// numbers become double. Keys are either hex hashes ("0x%016x") or names which are hashed.
+bpx_error_t bpx_sd_value_from_json(const char *json, bpx_sd_value_t *out);
+
+typedef struct bpx_sd_dump_options_s
+{
+    //Number of spaces per indentation level, 0 uses 2
+    bpx_size_t indent;
+    //Arrays and objects nested deeper than this are collapsed, 0 or anything above 256 uses 256
+    bpx_size_t max_depth;
+    //Maximum number of elements printed per array or object, 0 is unlimited
+    bpx_size_t max_elements;
+    //Optional table of key names, keys not found in it are printed as hex hashes
+    const char *const *symbols;
+    bpx_size_t symbol_count;
+} bpx_sd_dump_options_t;
+
+//Human-readable dump of a value with its type tags. options may be NULL. The returned string must be freed with
+// bpx_string_free (see bpx/utils.h).
+bpx_error_t bpx_sd_value_dump(const bpx_sd_value_t *value, const bpx_sd_dump_options_t *options, char **out);
+
 bpx_sd_value_t bpx_sd_value_new();
 bpx_sd_value_t bpx_sd_value_new_bool(bool value);
 bpx_sd_value_t bpx_sd_value_new_u8(bpx_u8_t value);
@@ -100,20 +159,20 @@ bpx_sd_value_t bpx_sd_value_new_double(double value);
 bpx_sd_value_t bpx_sd_value_new_string(const char *value);
 bpx_sd_value_t bpx_sd_value_new_array();
 bpx_sd_value_t bpx_sd_value_new_object();
-void bpx_sd_value_free(bpx_sd_value_t *value);
+bpx_error_t bpx_sd_value_free(bpx_sd_value_t *value);
 
-void bpx_sd_array_push(bpx_sd_array_t array, bpx_sd_value_t *value); //Takes ownership of value.
-void bpx_sd_array_insert(bpx_sd_array_t array, bpx_sd_value_t *value, bpx_size_t index); //Takes ownership of value.
-void bpx_sd_array_remove(bpx_sd_array_t array, bpx_size_t index);
+bpx_error_t bpx_sd_array_push(bpx_sd_array_t array, bpx_sd_value_t *value); //Takes ownership of value.
+bpx_error_t bpx_sd_array_insert(bpx_sd_array_t array, bpx_sd_value_t *value, bpx_size_t index); //Takes ownership of value.
+bpx_error_t bpx_sd_array_remove(bpx_sd_array_t array, bpx_size_t index);
 bpx_sd_value_t bpx_sd_array_get(bpx_sd_array_t array, bpx_size_t index);
 bpx_size_t bpx_sd_array_len(bpx_sd_array_t array);
-void bpx_sd_array_list(bpx_sd_array_t array, bpx_sd_value_t *out);
+bpx_error_t bpx_sd_array_list(bpx_sd_array_t array, bpx_sd_value_t *out);
 
 bpx_sd_value_t bpx_sd_object_get(bpx_sd_object_t object, const char *key);
-void bpx_sd_object_set(bpx_sd_object_t object, const char *key, bpx_sd_value_t *value); //Takes ownership of value.
+bpx_error_t bpx_sd_object_set(bpx_sd_object_t object, const char *key, bpx_sd_value_t *value); //Takes ownership of value.
 bpx_sd_value_t bpx_sd_object_rawget(bpx_sd_object_t object, bpx_u64_t hash);
-void bpx_sd_object_rawset(bpx_sd_object_t object, bpx_u64_t hash, bpx_sd_value_t *value); //Takes ownership of value.
+bpx_error_t bpx_sd_object_rawset(bpx_sd_object_t object, bpx_u64_t hash, bpx_sd_value_t *value); //Takes ownership of value.
 bpx_size_t bpx_sd_object_len(bpx_sd_object_t object);
-void bpx_sd_object_list(bpx_sd_object_t object, bpx_sd_object_entry_t *out);
+bpx_error_t bpx_sd_object_list(bpx_sd_object_t object, bpx_sd_object_entry_t *out);
 
 #endif
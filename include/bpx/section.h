@@ -30,14 +30,21 @@
 #define BPX_SECTION_H
 
 #include "bpx/types.h"
+#include "bpx/open2.h"
 
 /* Open/close sections */
 bpx_error_t bpx_section_open(bpx_container_t container, bpx_handle_t handle, bpx_section_t *out);
 bpx_error_t bpx_section_load(bpx_container_t container, bpx_handle_t handle, bpx_section_t *out);
-void bpx_section_close(bpx_section_t *section);
+bpx_error_t bpx_section_close(bpx_section_t *section);
+
+/* Zero-copy access to uncompressed sections of memory mapped containers, valid until the container is closed */
+//Containers are only memory mapped when opened by bpx_container_open_with_options with BPX_OPEN_READ_ONLY and mmap set
+//(see bpx/open.h), others fail with BPX_ERR_NOT_MMAP_BACKED. bpx_section_load and bpx_section_open still copy the
+//section, only this function reads it in place.
+bpx_error_t bpx_section_load_mapped(bpx_container_t container, bpx_handle_t handle, const bpx_u8_t **data, bpx_size_t *size);
 
 /* Immutable operations */
-void bpx_section_get_header(bpx_container_t container, bpx_handle_t handle, bpx_section_header_t *section_header);
+bpx_error_t bpx_section_get_header(bpx_container_t container, bpx_handle_t handle, bpx_section_header_t *section_header);
 bpx_size_t bpx_section_size(bpx_section_t section);
 
 /* Raw IO */
@@ -47,8 +54,28 @@ bpx_u64_t bpx_section_seek(bpx_section_t section, bpx_u64_t pos);
 int bpx_section_flush(bpx_section_t section);
 
 /* Specialized */
+//Writing, appending, truncating and shifting sections of a read-only container fails (SIZE_MAX or -1) with
+//BPX_ERR_READ_ONLY as the last error, see bpx/last_error.h.
 bpx_size_t bpx_section_write_append(bpx_section_t section, const bpx_u8_t *buffer, bpx_size_t size);
 int bpx_section_truncate(bpx_section_t section, bpx_size_t size, bpx_size_t *new_size);
 int bpx_section_shift(bpx_section_t section, bpx_i64_t amount);
 
+/* Same as above but returning error codes (inflate/deflate and not loaded errors use their BPX codes) */
+bpx_error_t bpx_section_read2(bpx_section_t section, bpx_u8_t *buffer, bpx_size_t size, bpx_size_t *bytes_read);
+bpx_error_t bpx_section_write2(bpx_section_t section, const bpx_u8_t *buffer, bpx_size_t size, bpx_size_t *bytes_written);
+bpx_error_t bpx_section_write_append2(bpx_section_t section, const bpx_u8_t *buffer, bpx_size_t size, bpx_size_t *bytes_written);
+bpx_error_t bpx_section_seek2(bpx_section_t section, bpx_seek_from_t whence, bpx_i64_t offset, bpx_u64_t *new_pos); //Positions past the end are out of range
+bpx_error_t bpx_section_tell(bpx_section_t section, bpx_u64_t *pos);
+bpx_error_t bpx_section_flush2(bpx_section_t section);
+bpx_error_t bpx_section_truncate2(bpx_section_t section, bpx_size_t size, bpx_size_t *new_size);
+bpx_error_t bpx_section_shift2(bpx_section_t section, bpx_i64_t amount);
+
+#ifdef __linux__
+#include <stdio.h>
+
+/* Stdio stream over a section (uses fopencookie). The section must stay open until fclose. Mode "w" truncates the
+ * section and mode "a" starts at its end. Returns NULL on failure (see bpx_get_last_error). */
+FILE *bpx_section_fopen(bpx_section_t section, const char *mode);
+#endif
+
 #endif
@@ -31,6 +31,7 @@
 
 typedef void* bpx_container_t;
 typedef void* bpx_section_t;
+typedef void* bpx_section_iter_t;
 
 #include <stdint.h>
 #include <stdlib.h>
@@ -45,12 +46,20 @@ typedef int16_t bpx_i16_t;
 typedef int32_t bpx_i32_t;
 typedef int64_t bpx_i64_t;
 
+//Identifies a section of the container which returned it; using it with another container or after its section was
+//removed fails with BPX_ERR_INVALID_HANDLE
 typedef bpx_u32_t bpx_handle_t;
 
 typedef size_t bpx_size_t;
 
 typedef unsigned int bpx_error_t;
 
+typedef struct bpx_buffer_s
+{
+    bpx_u8_t *data;
+    bpx_size_t size;
+} bpx_buffer_t;
+
 typedef struct bpx_main_header_s
 {
     bpx_u8_t signature[3];
@@ -32,5 +32,8 @@
 #include "bpx/types.h"
 
 bpx_u64_t bpx_hash(const char *str);
+bpx_error_t bpx_buffer_free(bpx_buffer_t *buffer);
+//Frees a string returned by the library and resets *str to NULL
+bpx_error_t bpx_string_free(char **str);
 
 #endif
\ No newline at end of file
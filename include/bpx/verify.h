@@ -0,0 +1,61 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+#ifndef BPX_VERIFY_H
+#define BPX_VERIFY_H
+
+#include "bpx/types.h"
+#include "bpx/open2.h"
+
+typedef struct bpx_section_failure_s
+{
+    bpx_handle_t handle;
+    bpx_error_t code;
+} bpx_section_failure_t;
+
+typedef struct bpx_verify_report_s
+{
+    bpx_error_t main_header; //BPX_ERR_NONE if the main header checksum matches
+    bpx_section_failure_t *failures;
+    bpx_size_t failure_count;
+} bpx_verify_report_t;
+
+//Returns BPX_ERR_CONTAINER_CORRUPTED if the report contains any failure. Sections are read straight from the backend and
+//are not loaded by verifying them.
+//Checks what the last save stored: sections written to since then are skipped, and a created container which was never
+//saved always passes.
+bpx_error_t bpx_container_verify(bpx_container_t container, bpx_verify_report_t *report);
+
+//Copies every section of src which still loads and passes its checksum into a new container written to dst, with the
+//same flags: compressed sections stay compressed.
+//The report lists the dropped sections (handles from src) and is filled whenever the return value is not an open error.
+bpx_error_t bpx_container_salvage(bpx_container_io_t src, bpx_container_io_t dst, bpx_verify_report_t *report);
+
+bpx_error_t bpx_verify_report_free(bpx_verify_report_t *report);
+
+#endif
@@ -0,0 +1,411 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cell::Cell;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use crate::io_wrapper::IoWrapper;
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct IoStats
+{
+    //Number of callback invocations actually performed
+    pub calls: u64,
+    //Number of reads and writes served by the buffer without invoking any callback
+    pub saved: u64
+}
+
+//Read-ahead and write-behind buffer over the ContainerIo callbacks; at most one of the read or write buffer is in use
+pub struct BufferedIo
+{
+    inner: IoWrapper,
+    buffer: Box<[u8]>,
+    read_pos: usize,
+    read_len: usize,
+    write_len: usize,
+    stats: Rc<Cell<IoStats>>
+}
+
+impl BufferedIo
+{
+    pub fn new(inner: IoWrapper, size: usize) -> BufferedIo
+    {
+        BufferedIo {
+            inner,
+            buffer: vec![0; size].into_boxed_slice(),
+            read_pos: 0,
+            read_len: 0,
+            write_len: 0,
+            stats: Rc::new(Cell::new(IoStats::default()))
+        }
+    }
+
+    pub fn stats(&self) -> Rc<Cell<IoStats>>
+    {
+        self.stats.clone()
+    }
+
+    fn count(&self, calls: u64, saved: u64)
+    {
+        let mut stats = self.stats.get();
+        stats.calls += calls;
+        stats.saved += saved;
+        self.stats.set(stats);
+    }
+
+    fn flush_writes(&mut self) -> std::io::Result<()>
+    {
+        let mut pos = 0;
+        while pos < self.write_len {
+            self.count(1, 0);
+            let len = self.inner.write(&self.buffer[pos..self.write_len]);
+            match len {
+                Ok(0) => {
+                    self.buffer.copy_within(pos..self.write_len, 0);
+                    self.write_len -= pos;
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                },
+                Ok(len) => pos += len,
+                Err(e) => {
+                    self.buffer.copy_within(pos..self.write_len, 0);
+                    self.write_len -= pos;
+                    return Err(e);
+                }
+            }
+        }
+        self.write_len = 0;
+        Ok(())
+    }
+
+    //The backend is ahead of the logical position by the unread part of the read buffer, which must be given back
+    // before anything else than a read reaches the backend.
+    fn discard_reads(&mut self) -> std::io::Result<()>
+    {
+        let remaining = (self.read_len - self.read_pos) as i64;
+        self.read_pos = 0;
+        self.read_len = 0;
+        if remaining > 0 {
+            self.count(1, 0);
+            self.inner.seek(SeekFrom::Current(-remaining))?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for BufferedIo
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        self.flush_writes()?;
+        if self.read_pos == self.read_len {
+            if buf.len() >= self.buffer.len() {
+                self.count(1, 0);
+                return self.inner.read(buf);
+            }
+            self.count(1, 0);
+            self.read_len = self.inner.read(&mut self.buffer)?;
+            self.read_pos = 0;
+        } else {
+            self.count(0, 1);
+        }
+        let len = std::cmp::min(buf.len(), self.read_len - self.read_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.read_pos..self.read_pos + len]);
+        self.read_pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for BufferedIo
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        self.discard_reads()?;
+        //Backends without a write callback must fail right away rather than on the next flush
+        if !self.inner.can_write() {
+            self.count(1, 0);
+            return self.inner.write(buf);
+        }
+        if self.write_len + buf.len() > self.buffer.len() {
+            self.flush_writes()?;
+            if buf.len() >= self.buffer.len() {
+                self.count(1, 0);
+                return self.inner.write(buf);
+            }
+        } else {
+            self.count(0, 1);
+        }
+        self.buffer[self.write_len..self.write_len + buf.len()].copy_from_slice(buf);
+        self.write_len += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        self.flush_writes()?;
+        self.count(1, 0);
+        self.inner.flush()
+    }
+}
+
+impl Seek for BufferedIo
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64>
+    {
+        self.flush_writes()?;
+        let remaining = (self.read_len - self.read_pos) as i64;
+        self.read_pos = 0;
+        self.read_len = 0;
+        let pos = match pos {
+            SeekFrom::Current(offset) => SeekFrom::Current(offset - remaining),
+            pos => pos
+        };
+        let calls = match pos {
+            SeekFrom::End(_) if self.inner.has_size() => 2,
+            _ => 1
+        };
+        self.count(calls, 0);
+        self.inner.seek(pos)
+    }
+}
+
+impl Drop for BufferedIo
+{
+    fn drop(&mut self)
+    {
+        //Same as std::io::BufWriter, errors cannot be reported from here
+        let _ = self.flush_writes();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::ffi::c_void;
+    use std::io::Cursor;
+    use std::os::raw::c_uint;
+    use crate::error_codes::{ERR_CORE_IO, ERR_NONE};
+    use crate::ffi_helper::callback;
+    use crate::io_wrapper::{ContainerIo2, CONTAINER_IO2_VERSION};
+    use super::*;
+
+    unsafe extern "C" fn seek(userdata: *const c_void, from: crate::io_wrapper::SeekFrom, offset: i64, new_pos: *mut u64) -> c_uint
+    {
+        let cursor = &mut *(userdata as *mut Cursor<Vec<u8>>);
+        let pos = match from {
+            crate::io_wrapper::SeekFrom::Start => SeekFrom::Start(offset as u64),
+            crate::io_wrapper::SeekFrom::End => SeekFrom::End(offset),
+            crate::io_wrapper::SeekFrom::Current => SeekFrom::Current(offset)
+        };
+        match cursor.seek(pos) {
+            Ok(pos) => {
+                *new_pos = pos;
+                ERR_NONE
+            },
+            Err(_) => ERR_CORE_IO
+        }
+    }
+
+    unsafe extern "C" fn read(userdata: *const c_void, buffer: *mut u8, size: usize, bytes_read: *mut usize) -> c_uint
+    {
+        let cursor = &mut *(userdata as *mut Cursor<Vec<u8>>);
+        match cursor.read(std::slice::from_raw_parts_mut(buffer, size)) {
+            Ok(len) => {
+                *bytes_read = len;
+                ERR_NONE
+            },
+            Err(_) => ERR_CORE_IO
+        }
+    }
+
+    unsafe extern "C" fn write(userdata: *const c_void, buffer: *const u8, size: usize, bytes_written: *mut usize) -> c_uint
+    {
+        let cursor = &mut *(userdata as *mut Cursor<Vec<u8>>);
+        match cursor.write(std::slice::from_raw_parts(buffer, size)) {
+            Ok(len) => {
+                *bytes_written = len;
+                ERR_NONE
+            },
+            Err(_) => ERR_CORE_IO
+        }
+    }
+
+    unsafe extern "C" fn flush(_: *const c_void) -> c_uint
+    {
+        ERR_NONE
+    }
+
+    unsafe extern "C" fn size(userdata: *const c_void, size: *mut u64) -> c_uint
+    {
+        let cursor = &*(userdata as *const Cursor<Vec<u8>>);
+        *size = cursor.get_ref().len() as u64;
+        ERR_NONE
+    }
+
+    unsafe extern "C" fn size_error(_: *const c_void, _: *mut u64) -> c_uint
+    {
+        42
+    }
+
+    //The cursor must outlive the returned BufferedIo
+    fn buffered(data: &mut Cursor<Vec<u8>>, size: usize) -> BufferedIo
+    {
+        buffered_with_size(data, size, None)
+    }
+
+    fn buffered_with_size(data: &mut Cursor<Vec<u8>>, buffer_size: usize, size: Option<callback!((userdata: *const c_void, size: *mut u64) -> c_uint)>) -> BufferedIo
+    {
+        let io = IoWrapper::new2(ContainerIo2 {
+            version: CONTAINER_IO2_VERSION,
+            userdata: data as *mut Cursor<Vec<u8>> as *const c_void,
+            seek,
+            read,
+            write: Some(write),
+            flush: Some(flush),
+            size,
+            close: None,
+            buffer_size
+        });
+        BufferedIo::new(io, buffer_size)
+    }
+
+    fn sequence(len: u8) -> Cursor<Vec<u8>>
+    {
+        Cursor::new((0..len).collect())
+    }
+
+    #[test]
+    fn read_after_write()
+    {
+        let mut data = sequence(64);
+        let mut io = buffered(&mut data, 8);
+        io.write_all(&[0xAA; 3]).unwrap();
+        let mut buf = [0; 4];
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [3, 4, 5, 6]);
+        //The backend is ahead by the unread part of the read buffer
+        io.write_all(&[0xBB; 2]).unwrap();
+        assert_eq!(io.stream_position().unwrap(), 9);
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [9, 10, 11, 12]);
+        drop(io);
+        let data = data.into_inner();
+        assert_eq!(&data[..10], [0xAA, 0xAA, 0xAA, 3, 4, 5, 6, 0xBB, 0xBB, 9]);
+        assert_eq!(data.len(), 64);
+    }
+
+    #[test]
+    fn seek_current_with_unread_data()
+    {
+        let mut data = sequence(64);
+        let mut io = buffered(&mut data, 8);
+        let mut buf = [0; 2];
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0, 1]);
+        assert_eq!(io.seek(SeekFrom::Current(3)).unwrap(), 5);
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [5, 6]);
+        assert_eq!(io.seek(SeekFrom::Current(-4)).unwrap(), 3);
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [3, 4]);
+        assert_eq!(io.stream_position().unwrap(), 5);
+        assert_eq!(io.seek(SeekFrom::End(-1)).unwrap(), 63);
+        io.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 63);
+    }
+
+    #[test]
+    fn large_writes_and_reads()
+    {
+        let mut data = Cursor::new(Vec::new());
+        let mut io = buffered(&mut data, 8);
+        let payload: Vec<u8> = (0..20).collect();
+        io.write_all(&[0xAA; 3]).unwrap();
+        //Does not fit in the buffer: the pending bytes are flushed first, then the payload is written directly
+        assert_eq!(io.write(&payload).unwrap(), 20);
+        io.write_all(&[0xBB; 2]).unwrap();
+        assert_eq!(io.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let mut buf = [0; 25];
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..3], [0xAA; 3]);
+        assert_eq!(buf[3..23], payload[..]);
+        assert_eq!(buf[23..], [0xBB; 2]);
+        drop(io);
+        assert_eq!(data.into_inner().len(), 25);
+    }
+
+    #[test]
+    fn stats()
+    {
+        let mut data = sequence(64);
+        let mut io = buffered(&mut data, 8);
+        let stats = io.stats();
+        let mut buf = [0; 2];
+        io.read_exact(&mut buf).unwrap();
+        io.read_exact(&mut buf).unwrap();
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(stats.get().calls, 1);
+        assert_eq!(stats.get().saved, 2);
+        io.seek(SeekFrom::Start(0)).unwrap();
+        io.write_all(&buf).unwrap();
+        assert_eq!(stats.get().calls, 2);
+        assert_eq!(stats.get().saved, 3);
+        io.flush().unwrap();
+        assert_eq!(stats.get().calls, 4);
+        assert_eq!(stats.get().saved, 3);
+        //Reads at least as large as the buffer bypass it
+        let mut buf = [0; 8];
+        io.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(stats.get().calls, 5);
+        assert_eq!(stats.get().saved, 3);
+    }
+
+    #[test]
+    fn size_only_for_end_seeks()
+    {
+        let mut data = sequence(64);
+        let mut io = buffered_with_size(&mut data, 8, Some(size));
+        let stats = io.stats();
+        assert_eq!(io.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(io.seek(SeekFrom::Current(2)).unwrap(), 6);
+        assert_eq!(stats.get().calls, 2);
+        assert_eq!(io.seek(SeekFrom::End(-2)).unwrap(), 62);
+        assert_eq!(stats.get().calls, 4);
+        drop(io);
+        //A failing size callback must not affect other seeks nor leave an error behind
+        let mut data = sequence(64);
+        let mut io = buffered_with_size(&mut data, 8, Some(size_error));
+        let error = io.inner.last_error();
+        assert_eq!(io.seek(SeekFrom::Start(4)).unwrap(), 4);
+        assert_eq!(io.seek(SeekFrom::Current(2)).unwrap(), 6);
+        assert_eq!(error.get(), ERR_NONE);
+        assert!(io.seek(SeekFrom::End(-2)).is_err());
+        assert_eq!(error.get(), 42);
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+//Backend over a C stdio stream; going through stdio keeps the stream buffer and position consistent for the caller
+pub struct CFileIo
+{
+    file: *mut libc::FILE,
+    owned: bool
+}
+
+impl CFileIo
+{
+    pub fn new(file: *mut libc::FILE, owned: bool) -> CFileIo
+    {
+        CFileIo {
+            file,
+            owned
+        }
+    }
+
+    //Returns true when the underlying descriptor is known to be opened for reading only
+    #[cfg(unix)]
+    pub fn is_read_only(&self) -> bool
+    {
+        unsafe {
+            let flags = libc::fcntl(libc::fileno(self.file), libc::F_GETFL);
+            flags != -1 && flags & libc::O_ACCMODE == libc::O_RDONLY
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn is_read_only(&self) -> bool
+    {
+        false
+    }
+
+    fn check(&self, len: usize, expected: usize) -> std::io::Result<usize>
+    {
+        if len < expected && unsafe { libc::ferror(self.file) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(len)
+    }
+}
+
+impl Read for CFileIo
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let len = unsafe { libc::fread(buf.as_mut_ptr() as _, 1, buf.len(), self.file) };
+        self.check(len, buf.len())
+    }
+}
+
+impl Write for CFileIo
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        let len = unsafe { libc::fwrite(buf.as_ptr() as _, 1, buf.len(), self.file) };
+        self.check(len, buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        match unsafe { libc::fflush(self.file) } {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error())
+        }
+    }
+}
+
+impl Seek for CFileIo
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64>
+    {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(v) => (i64::try_from(v).map_err(|_| Error::new(ErrorKind::InvalidInput, "Seek position is too large"))?, libc::SEEK_SET),
+            SeekFrom::End(v) => (v, libc::SEEK_END),
+            SeekFrom::Current(v) => (v, libc::SEEK_CUR)
+        };
+        #[cfg(unix)]
+        let pos = unsafe {
+            if libc::fseeko(self.file, offset as libc::off_t, whence) != 0 {
+                return Err(Error::last_os_error());
+            }
+            libc::ftello(self.file) as i64
+        };
+        #[cfg(not(unix))]
+        let pos = unsafe {
+            let offset = libc::c_long::try_from(offset).map_err(|_| Error::new(ErrorKind::InvalidInput, "Seek offset is too large"))?;
+            if libc::fseek(self.file, offset, whence) != 0 {
+                return Err(Error::last_os_error());
+            }
+            libc::ftell(self.file) as i64
+        };
+        if pos < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(pos as u64)
+    }
+}
+
+impl Drop for CFileIo
+{
+    fn drop(&mut self)
+    {
+        unsafe {
+            if self.owned {
+                libc::fclose(self.file);
+            } else {
+                //The caller keeps using the stream, make sure it sees everything written through it
+                libc::fflush(self.file);
+            }
+        }
+    }
+}
@@ -26,15 +26,30 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::os::raw::c_uint;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use bpx::core::builder::{Checksum, CompressionMethod, SectionHeaderBuilder};
-use crate::types::{Container, Handle};
+use crate::types::{Buffer, Container, Handle};
 use crate::error_codes::unwrap_or_err;
-use crate::error_codes::{CErrCode, ERR_NONE};
+use crate::error_codes::{CErrCode, ERR_FILE_CREATE, ERR_NONE, ERR_NOT_MEMORY_BACKED};
+use crate::buffered_io::IoStats;
+use crate::container_wrapper::ContainerWrapper;
+use crate::io_wrapper::{ContainerIo, IoWrapper};
+use crate::open::{create_container, file_error, ContainerOptions};
+use crate::last_error::LastError;
+use crate::memory_io::MemoryIo;
+use crate::path_utils::cstr_to_path;
+use crate::raw_section;
+use crate::raw_section::RawSection;
 use crate::types::MainHeader;
 use crate::ffi_helper::export;
 use crate::ffi_helper::export_object;
 use crate::ffi_helper::OutCell;
+use crate::ffi_helper::Object;
 
 #[repr(C)]
 pub struct SectionOptions
@@ -51,9 +66,214 @@ pub const CHECKSUM_WEAK: u8 = 0x4;
 pub const CHECKSUM_CRC32: u8 = 0x8;
 pub const COMPRESSION_THRESHOLD: u8 = 0x10;
 
+pub const SECTION_TYPE_ANY: c_int = -1;
+
+#[repr(C)]
+pub struct SectionFilter
+{
+    pub by_type: bool,
+    pub ty: u8,
+    //Only sections with header.flags & flags_mask == flags are matched
+    pub flags_mask: u8,
+    pub flags: u8
+}
+
+impl SectionFilter
+{
+    pub fn matches(&self, ty: u8, flags: u8) -> bool
+    {
+        (!self.by_type || self.ty == ty) && flags & self.flags_mask == self.flags
+    }
+}
+
+//Snapshot of the matching handles at the time the iterator was created
+pub struct SectionIter
+{
+    handles: Vec<Handle>,
+    pos: usize
+}
+
+//Options creating a section like the one described by header, compressed sections stay compressed whatever their size
+pub fn copy_options(header: &bpx::core::header::SectionHeader) -> SectionOptions
+{
+    let flags = match header.flags & (COMPRESSION_ZLIB | COMPRESSION_XZ) {
+        0 => header.flags,
+        _ => header.flags | COMPRESSION_THRESHOLD
+    };
+    SectionOptions {
+        size: header.size,
+        ty: header.ty,
+        flags,
+        threshold: 0
+    }
+}
+
+pub fn section_header_builder(options: &SectionOptions) -> SectionHeaderBuilder
+{
+    let mut builder = SectionHeaderBuilder::new();
+    builder.ty(options.ty).size(options.size);
+    if options.flags & CHECKSUM_CRC32 != 0 {
+        builder.checksum(Checksum::Crc32);
+    }
+    if options.flags & CHECKSUM_WEAK != 0 {
+        builder.checksum(Checksum::Weak);
+    }
+    if options.flags & COMPRESSION_ZLIB != 0 {
+        builder.compression(CompressionMethod::Zlib);
+    }
+    if options.flags & COMPRESSION_XZ != 0 {
+        builder.compression(CompressionMethod::Xz);
+    }
+    if options.flags & COMPRESSION_THRESHOLD != 0 {
+        builder.threshold(options.threshold);
+    }
+    builder
+}
+
+//The stored payload of a section can be reused when encoding it again with options would compress it the same way
+fn keeps_payload(header: &bpx::core::header::SectionHeader, options: &SectionOptions) -> bool
+{
+    header.flags & (COMPRESSION_ZLIB | COMPRESSION_XZ) != 0
+        && options.flags & !COMPRESSION_THRESHOLD == header.flags
+        && options.flags & COMPRESSION_THRESHOLD != 0
+        && header.size > options.threshold
+}
+
+//Returns the header and stored payload of the section when copying it with options can skip re-encoding it
+pub fn stored_payload(src: &Container, handle: bpx::core::Handle, options: &SectionOptions) -> Result<Option<(bpx::core::header::SectionHeader, Vec<u8>)>, c_uint>
+{
+    match keeps_payload(&src.section_header(handle), options) {
+        true => raw_section::read_payload(src, handle),
+        false => Ok(None)
+    }
+}
+
+//Adds a section to dst which is written with the given header and payload without going through its encoder
+pub fn insert_raw_section(dst: &mut Container, header: bpx::core::header::SectionHeader, payload: Vec<u8>) -> Result<bpx::core::Handle, c_uint>
+{
+    let new_handle = dst.create_section(section_header_builder(&SectionOptions {
+        size: payload.len() as u32,
+        ty: header.ty,
+        flags: 0,
+        threshold: 0
+    }));
+    if let Err(e) = write_section(dst, new_handle, &payload[..]) {
+        dst.remove_section(new_handle);
+        return Err(e);
+    }
+    dst.set_raw_section(new_handle, RawSection::Stored(header));
+    Ok(new_handle)
+}
+
+//Copies a section into a new section of dst, either as is (see stored_payload) or through its uncompressed content.
+// src and dst may be the same container: it is only borrowed mutably while no section is borrowed.
+pub unsafe fn copy_section(src: *const Container, handle: bpx::core::Handle, dst: *mut Container, options: &SectionOptions) -> Result<bpx::core::Handle, c_uint>
+{
+    if let Some((header, payload)) = stored_payload(&*src, handle, options)? {
+        return insert_raw_section(&mut *dst, header, payload);
+    }
+    (*src).decode_raw(handle)?;
+    let new_handle = (*dst).create_section(section_header_builder(options));
+    let res = (*src).sections().load(handle).map_err(|e| e.cerr_code()).and_then(|mut data| {
+        data.seek(SeekFrom::Start(0)).map_err(|e| e.cerr_code())?;
+        write_section(&*dst, new_handle, &mut *data)
+    });
+    if let Err(e) = res {
+        (*dst).remove_section(new_handle); //Do not leave a partially written section behind
+        return Err(e);
+    }
+    Ok(new_handle)
+}
+
+//Writes a copy of src into a new container backed by wrapper
+pub unsafe fn save_into(src: &Container, wrapper: ContainerWrapper) -> Result<Container, c_uint>
+{
+    rewrite(src, wrapper, |v| {
+        let options = copy_options(&src.section_header(v));
+        (options.flags, options.threshold)
+    })
+}
+
+//Same as save_into but flags returns the section flags and compression threshold to use for each section
+pub unsafe fn rewrite(src: &Container, wrapper: ContainerWrapper, flags: impl Fn(bpx::core::Handle) -> (u8, u32)) -> Result<Container, c_uint>
+{
+    src.check_no_open_sections()?;
+    let header = src.get_main_header();
+    let mut dst = create_container(wrapper, &ContainerOptions {
+        ty: header.ty,
+        version: header.version,
+        type_ext: header.type_ext
+    });
+    for handle in src.sections().iter() {
+        let header = src.section_header(handle);
+        let (flags, threshold) = flags(handle);
+        copy_section(src, handle, &mut dst, &SectionOptions {
+            size: header.size,
+            ty: header.ty,
+            flags,
+            threshold
+        })?;
+    }
+    dst.save_changes()?;
+    Ok(dst)
+}
+
+//Creates a new file next to path, named after the process and a per-process counter so that concurrent saves to the
+// same path never share (or truncate) each other's temporary file
+fn create_temp_file(path: &Path) -> Result<(PathBuf, File), c_uint>
+{
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{}.{}.tmp", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp = PathBuf::from(tmp);
+        match File::options().read(true).write(true).create_new(true).open(&tmp) {
+            Ok(f) => return Ok((tmp, f)),
+            //Left behind by a previous process with the same id
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(file_error(ERR_FILE_CREATE, e))
+        }
+    }
+}
+
+//Saves into a temporary file next to path which then replaces path, so that path is never left half-written
+unsafe fn save_as(src: &Container, path: &Path) -> Result<(), c_uint>
+{
+    let (tmp, f) = create_temp_file(path)?;
+    let res = save_into(src, ContainerWrapper::from(f)).and_then(|dst| {
+        match dst.into_backend() {
+            Some(ContainerWrapper::File(f)) => f.sync_all().map_err(|e| file_error(ERR_FILE_CREATE, e)),
+            _ => Ok(())
+        }
+    }).and_then(|_| std::fs::rename(&tmp, path).map_err(|e| file_error(ERR_FILE_CREATE, e)));
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+        return res;
+    }
+    //Make the rename itself durable
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v,
+            _ => Path::new(".")
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+fn write_section(dst: &Container, handle: bpx::core::Handle, mut data: impl Read) -> Result<(), c_uint>
+{
+    let mut section = dst.sections().open(handle).map_err(|e| e.cerr_code())?;
+    std::io::copy(&mut data, &mut *section).map_err(|e| e.cerr_code())?;
+    Ok(())
+}
+
 export_object! {
     Container {
-        fn bpx_container_get_main_header(this, main_header: OutCell<MainHeader>) {
+        fn bpx_container_get_main_header(this, main_header: OutCell<MainHeader>) -> c_uint {
             main_header.set(MainHeader {
                 section_num: this.get_main_header().section_num,
                 version: this.get_main_header().version,
@@ -63,17 +283,57 @@ export_object! {
                 type_ext: this.get_main_header().type_ext,
                 file_size: this.get_main_header().file_size
             });
+            ERR_NONE
         }
 
-        fn bpx_container_list_sections(this, out: *mut Handle, size: usize) {
-            this.sections().iter().map(|v| v.into_raw()).take(size).enumerate().for_each(|(i, v)| {
+        fn bpx_container_list_sections(this, out: *mut Handle, size: usize) -> c_uint {
+            this.sections().iter().map(|v| this.export_handle(v)).take(size).enumerate().for_each(|(i, v)| {
                 std::ptr::write(out.add(i), v);
             });
+            ERR_NONE
+        }
+
+        fn bpx_container_save_as(this, file: *const c_char) -> c_uint {
+            let path = unwrap_or_err!(cstr_to_path(CStr::from_ptr(file)));
+            unwrap_or_err!(save_as(this, path));
+            ERR_NONE
+        }
+
+        fn bpx_container_save_to_io(this, io: ContainerIo) -> c_uint {
+            unwrap_or_err!(save_into(this, ContainerWrapper::from(IoWrapper::new(io))));
+            ERR_NONE
+        }
+
+        fn bpx_container_get_io_error(this) -> c_uint {
+            this.io_error()
+        }
+
+        fn bpx_container_get_io_stats(this, stats: OutCell<IoStats>) -> c_uint {
+            stats.set(this.io_stats());
+            ERR_NONE
+        }
+
+        fn bpx_container_count_sections(this, ty: c_int) -> usize {
+            this.sections().iter().filter(|v| ty == SECTION_TYPE_ANY || this.sections().header(*v).ty as c_int == ty).count()
+        }
+
+        //filter may be NULL to iterate over all sections
+        fn bpx_section_iter_new(this, filter: *const SectionFilter, out: OutCell<Object<SectionIter>>) -> c_uint {
+            let filter = filter.as_ref();
+            let handles = this.sections().iter().filter(|v| {
+                let header = this.section_header(*v);
+                filter.map_or(true, |f| f.matches(header.ty, header.flags))
+            }).map(|v| this.export_handle(v)).collect();
+            out.set(Object::new(SectionIter {
+                handles,
+                pos: 0
+            }));
+            ERR_NONE
         }
 
         fn bpx_container_find_section_by_type(this, ty: u8, handle: OutCell<Handle>) -> bool {
             if let Some(v) = this.sections().find_by_type(ty) {
-                handle.set(v.into_raw());
+                handle.set(this.export_handle(v));
                 true
             } else {
                 false
@@ -82,44 +342,219 @@ export_object! {
 
         fn bpx_container_find_section_by_index(this, idx: u32, handle: OutCell<Handle>) -> bool {
             if let Some(v) = this.sections().find_by_index(idx) {
-                handle.set(v.into_raw());
+                handle.set(this.export_handle(v));
                 true
             } else {
                 false
             }
         }
 
-        mut fn bpx_container_create_section(this, options: *const SectionOptions) -> Handle {
-            let options = &*options;
-            let mut builder = SectionHeaderBuilder::new();
-            builder.ty(options.ty).size(options.size);
-            if options.flags & CHECKSUM_CRC32 != 0 {
-                builder.checksum(Checksum::Crc32);
-            }
-            if options.flags & CHECKSUM_WEAK != 0 {
-                builder.checksum(Checksum::Weak);
-            }
-            if options.flags & COMPRESSION_ZLIB != 0 {
-                builder.compression(CompressionMethod::Zlib);
-            }
-            if options.flags & COMPRESSION_XZ != 0 {
-                builder.compression(CompressionMethod::Xz);
-            }
-            if options.flags & COMPRESSION_THRESHOLD != 0 {
-                builder.threshold(options.threshold);
-            }
-            this.sections_mut().create(builder).into_raw()
+        mut fn bpx_container_create_section(this, options: *const SectionOptions, handle: OutCell<Handle>) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            unwrap_or_err!(this.check_no_open_sections());
+            let builder = section_header_builder(&*options);
+            let new_handle = this.create_section(builder);
+            handle.set(this.export_handle(new_handle));
+            ERR_NONE
         }
 
-        mut fn bpx_container_remove_section(this, handle: Handle) {
-            this.sections_mut().remove(bpx::core::Handle::from_raw(handle));
+        mut fn bpx_container_remove_section(this, handle: Handle) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            unwrap_or_err!(this.check_no_open_sections());
+            let handle = unwrap_or_err!(this.handle(handle));
+            this.remove_section(handle);
+            ERR_NONE
         }
 
         mut fn bpx_container_save(this) -> c_uint {
-            unwrap_or_err!(this.save().map_err(|e| e.cerr_code()));
+            unwrap_or_err!(this.check_writable());
+            unwrap_or_err!(this.check_no_open_sections());
+            unwrap_or_err!(this.save_changes());
+            ERR_NONE
+        }
+    }
+}
+
+export_object! {
+    SectionIter {
+        fn bpx_section_iter_len(this) -> usize {
+            this.handles.len()
+        }
+
+        mut fn bpx_section_iter_next(this, handle: OutCell<Handle>) -> bool {
+            match this.handles.get(this.pos) {
+                Some(v) => {
+                    handle.set(*v);
+                    this.pos += 1;
+                    true
+                },
+                None => false
+            }
+        }
+
+        mut fn bpx_section_iter_reset(this) -> c_uint {
+            this.pos = 0;
             ERR_NONE
         }
 
-        close bpx_container_close(this) {}
+        close bpx_section_iter_close(this) {}
+    }
+}
+
+export! {
+    //src and dst may be the same container
+    fn bpx_container_copy_section(src: *const Container, handle: Handle, dst: *mut Container, out: OutCell<Handle>) -> c_uint {
+        unwrap_or_err!((*dst).check_writable());
+        unwrap_or_err!((*dst).check_no_open_sections());
+        let handle = unwrap_or_err!((*src).handle(handle));
+        let options = copy_options(&(*src).section_header(handle));
+        let new_handle = unwrap_or_err!(copy_section(src, handle, dst, &options));
+        out.set((*dst).export_handle(new_handle));
+        ERR_NONE
+    }
+
+    //Fails without closing anything if some sections of this container are still open.
+    fn bpx_container_close(container: *mut *mut Container) -> c_uint {
+        unwrap_or_err!((**container).check_no_open_sections());
+        let host = Box::from_raw(*container);
+        drop(host); // deallocate the heap wrapper
+        std::ptr::write(container, std::ptr::null_mut()); // reset user pointer to NULL
+        ERR_NONE
+    }
+
+    //Fails and leaves the container open if it was not created in memory or if some of its sections are still open.
+    fn bpx_container_into_buffer(container: *mut *mut Container, out: OutCell<Buffer>) -> c_uint {
+        unwrap_or_err!((**container).check_no_open_sections());
+        if !matches!(*(**container).backend(), ContainerWrapper::Memory(MemoryIo::Owned(_))) {
+            return LastError::new(ERR_NOT_MEMORY_BACKED, "container is not backed by a library owned memory buffer").set();
+        }
+        let host = Box::from_raw(*container);
+        std::ptr::write(container, std::ptr::null_mut()); // reset user pointer to NULL
+        let data = match host.into_backend() {
+            Some(ContainerWrapper::Memory(v)) => v.into_vec(),
+            _ => None
+        };
+        out.set(Buffer::new(data.unwrap_or_default()));
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+    use crate::open::open_container;
+    use crate::test_utils;
+    use super::*;
+
+    //A saved container with a zlib section, which therefore is copied as a raw section
+    fn compressed() -> (Container, bpx::core::Handle, Vec<u8>)
+    {
+        let mut container = test_utils::create();
+        let content = test_utils::content(8192, 1);
+        test_utils::add_section(&mut container, COMPRESSION_ZLIB | CHECKSUM_CRC32, &content);
+        let container = test_utils::reopen(container);
+        let handle = container.sections().find_by_index(0).unwrap();
+        (container, handle, content)
+    }
+
+    fn temp_path(name: &str) -> PathBuf
+    {
+        std::env::temp_dir().join(format!("bpx-c-{}-{}.bpx", std::process::id(), name))
+    }
+
+    #[test]
+    fn raw_copy()
+    {
+        let (src, handle, content) = compressed();
+        let mut dst = test_utils::create();
+        let new_handle = unsafe { copy_section(&src, handle, &mut dst, &copy_options(&src.section_header(handle))) }.unwrap();
+        assert!(dst.raw_section(new_handle).is_some());
+        assert_eq!(dst.section_header(new_handle).flags, src.section_header(handle).flags);
+        //Readable before save...
+        assert_eq!(test_utils::read(&dst, new_handle), content);
+        //...after save...
+        dst.save_changes().unwrap();
+        let new_handle = dst.sections().find_by_index(0).unwrap();
+        assert_eq!(test_utils::read(&dst, new_handle), content);
+        //...and after reopening, with the payload stored as is
+        let dst = test_utils::reopen(dst);
+        let new_handle = dst.sections().find_by_index(0).unwrap();
+        let header = *dst.sections().header(new_handle);
+        let src_header = *src.sections().header(handle);
+        assert_eq!((header.flags, header.size, header.csize, header.chksum), (src_header.flags, src_header.size, src_header.csize, src_header.chksum));
+        assert_eq!(raw_section::read_stored(&dst, &header).unwrap(), raw_section::read_stored(&src, &src_header).unwrap());
+        assert_eq!(test_utils::read(&dst, new_handle), content);
+    }
+
+    #[test]
+    fn raw_copy_into_same_container()
+    {
+        let (mut container, handle, content) = compressed();
+        let ptr: *mut Container = &mut container;
+        let options = copy_options(&container.section_header(handle));
+        let new_handle = unsafe { copy_section(ptr, handle, ptr, &options) }.unwrap();
+        assert_eq!(test_utils::read(&container, new_handle), content);
+        let container = test_utils::reopen(container);
+        assert_eq!(container.sections().len(), 2);
+        for handle in container.sections().iter() {
+            assert_eq!(test_utils::read(&container, handle), content);
+        }
+    }
+
+    #[test]
+    fn copy_with_other_flags()
+    {
+        let (src, handle, content) = compressed();
+        let mut dst = test_utils::create();
+        let new_handle = unsafe { copy_section(&src, handle, &mut dst, &SectionOptions {
+            size: content.len() as u32,
+            ty: 1,
+            flags: COMPRESSION_XZ | COMPRESSION_THRESHOLD,
+            threshold: 0
+        }) }.unwrap();
+        assert!(dst.raw_section(new_handle).is_none());
+        let dst = test_utils::reopen(dst);
+        let new_handle = dst.sections().find_by_index(0).unwrap();
+        assert_eq!(dst.sections().header(new_handle).flags & (COMPRESSION_ZLIB | COMPRESSION_XZ), COMPRESSION_XZ);
+        assert_eq!(test_utils::read(&dst, new_handle), content);
+    }
+
+    #[test]
+    fn save_as_file()
+    {
+        let path = temp_path("save-as");
+        std::fs::write(&path, b"previous content").unwrap();
+        let (container, _, content) = compressed();
+        unsafe { save_as(&container, &path) }.unwrap();
+        let saved = open_container(ContainerWrapper::from(File::open(&path).unwrap()), true).unwrap();
+        let handle = saved.sections().find_by_index(0).unwrap();
+        assert_eq!(saved.sections().header(handle).flags & COMPRESSION_ZLIB, COMPRESSION_ZLIB);
+        assert_eq!(test_utils::read(&saved, handle), content);
+        drop(saved);
+        //No temporary file is left behind
+        let prefix = path.file_name().unwrap().to_str().unwrap().to_owned() + ".";
+        let leftovers = std::fs::read_dir(std::env::temp_dir()).unwrap()
+            .filter(|v| v.as_ref().unwrap().file_name().to_str().map_or(false, |v| v.starts_with(&prefix)))
+            .count();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn save_as_keeps_the_source()
+    {
+        let path = temp_path("save-as-source");
+        let mut container = test_utils::create();
+        let content = test_utils::content(256, 3);
+        let handle = test_utils::add_section(&mut container, 0, &content);
+        //Unsaved changes are written to path, the source is left untouched
+        unsafe { save_as(&container, &path) }.unwrap();
+        assert!(container.is_modified(handle));
+        assert!(!container.is_saved());
+        let saved = open_container(ContainerWrapper::from(File::open(&path).unwrap()), true).unwrap();
+        assert_eq!(test_utils::read(&saved, saved.sections().find_by_index(0).unwrap()), content);
+        drop(saved);
+        std::fs::remove_file(&path).unwrap();
     }
 }
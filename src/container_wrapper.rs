@@ -26,14 +26,24 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::cell::{RefCell, RefMut};
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use crate::buffered_io::BufferedIo;
+use crate::c_file_io::CFileIo;
 use crate::io_wrapper::IoWrapper;
+use crate::memory_io::MemoryIo;
+use crate::mmap_io::MmapIo;
 
 pub enum ContainerWrapper
 {
     File(File),
-    IoWrapper(IoWrapper)
+    IoWrapper(IoWrapper),
+    Buffered(BufferedIo),
+    Memory(MemoryIo),
+    Mmap(MmapIo),
+    CFile(CFileIo)
 }
 
 impl From<File> for ContainerWrapper
@@ -52,13 +62,49 @@ impl From<IoWrapper> for ContainerWrapper
     }
 }
 
+impl From<BufferedIo> for ContainerWrapper
+{
+    fn from(v: BufferedIo) -> Self
+    {
+        Self::Buffered(v)
+    }
+}
+
+impl From<MemoryIo> for ContainerWrapper
+{
+    fn from(v: MemoryIo) -> Self
+    {
+        Self::Memory(v)
+    }
+}
+
+impl From<MmapIo> for ContainerWrapper
+{
+    fn from(v: MmapIo) -> Self
+    {
+        Self::Mmap(v)
+    }
+}
+
+impl From<CFileIo> for ContainerWrapper
+{
+    fn from(v: CFileIo) -> Self
+    {
+        Self::CFile(v)
+    }
+}
+
 impl Read for ContainerWrapper
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
     {
         match self {
             ContainerWrapper::File(v) => v.read(buf),
-            ContainerWrapper::IoWrapper(v) => v.read(buf)
+            ContainerWrapper::IoWrapper(v) => v.read(buf),
+            ContainerWrapper::Buffered(v) => v.read(buf),
+            ContainerWrapper::Memory(v) => v.read(buf),
+            ContainerWrapper::Mmap(v) => v.read(buf),
+            ContainerWrapper::CFile(v) => v.read(buf)
         }
     }
 }
@@ -69,7 +115,11 @@ impl Write for ContainerWrapper
     {
         match self {
             ContainerWrapper::File(v) => v.write(buf),
-            ContainerWrapper::IoWrapper(v) => v.write(buf)
+            ContainerWrapper::IoWrapper(v) => v.write(buf),
+            ContainerWrapper::Buffered(v) => v.write(buf),
+            ContainerWrapper::Memory(v) => v.write(buf),
+            ContainerWrapper::Mmap(v) => v.write(buf),
+            ContainerWrapper::CFile(v) => v.write(buf)
         }
     }
 
@@ -77,7 +127,11 @@ impl Write for ContainerWrapper
     {
         match self {
             ContainerWrapper::File(v) => v.flush(),
-            ContainerWrapper::IoWrapper(v) => v.flush()
+            ContainerWrapper::IoWrapper(v) => v.flush(),
+            ContainerWrapper::Buffered(v) => v.flush(),
+            ContainerWrapper::Memory(v) => v.flush(),
+            ContainerWrapper::Mmap(v) => v.flush(),
+            ContainerWrapper::CFile(v) => v.flush()
         }
     }
 }
@@ -88,7 +142,64 @@ impl Seek for ContainerWrapper
     {
         match self {
             ContainerWrapper::File(v) => v.seek(pos),
-            ContainerWrapper::IoWrapper(v) => v.seek(pos)
+            ContainerWrapper::IoWrapper(v) => v.seek(pos),
+            ContainerWrapper::Buffered(v) => v.seek(pos),
+            ContainerWrapper::Memory(v) => v.seek(pos),
+            ContainerWrapper::Mmap(v) => v.seek(pos),
+            ContainerWrapper::CFile(v) => v.seek(pos)
         }
     }
 }
+
+//Backend shared between a BPX container and its wrapper, so that the wrapper can still reach the raw file for the few
+// operations BPX has no API for
+#[derive(Clone)]
+pub struct SharedWrapper(Rc<RefCell<ContainerWrapper>>);
+
+impl SharedWrapper
+{
+    pub fn new(inner: ContainerWrapper) -> SharedWrapper
+    {
+        SharedWrapper(Rc::new(RefCell::new(inner)))
+    }
+
+    pub fn borrow_mut(&self) -> RefMut<'_, ContainerWrapper>
+    {
+        self.0.borrow_mut()
+    }
+
+    //Only succeeds once every other reference (including the one held by the BPX container) is dropped
+    pub fn into_inner(self) -> Option<ContainerWrapper>
+    {
+        Rc::try_unwrap(self.0).ok().map(|v| v.into_inner())
+    }
+}
+
+impl Read for SharedWrapper
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedWrapper
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl Seek for SharedWrapper
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64>
+    {
+        self.0.borrow_mut().seek(pos)
+    }
+}
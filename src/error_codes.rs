@@ -26,17 +26,21 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::io::ErrorKind;
 use std::os::raw::c_uint;
 use bpx::core::error::{DeflateError, Error, InflateError, OpenError};
+use crate::io_wrapper::UserError;
+use crate::last_error::LastError;
 
 // No error
 pub const ERR_NONE: c_uint = 0x0;
 
 // Local errors
 #[cfg(windows)]
-pub const ERR_INVALID_PATH: c_int = 0x1;
+pub const ERR_INVALID_PATH: c_uint = 0x1;
 pub const ERR_FILE_OPEN: c_uint = 0x2;
 pub const ERR_FILE_CREATE: c_uint = 0x3;
+pub const ERR_READ_ONLY: c_uint = 0x4;
 
 // BPX errors
 pub const ERR_CORE_CHKSUM: c_uint = 0x5;
@@ -71,16 +75,51 @@ pub const ERR_SD_UTF8: c_uint = 0x1C;
 pub const ERR_SD_CAPACITY_EXCEEDED: c_uint = 0x1D;
 pub const ERR_SD_NOT_AN_OBJECT: c_uint = 0x1E;
 
+// Backend errors
+pub const ERR_NOT_MEMORY_BACKED: c_uint = 0x1F;
+pub const ERR_NOT_MMAP_BACKED: c_uint = 0x20;
+pub const ERR_SECTION_COMPRESSED: c_uint = 0x21;
+
+// Section errors
+pub const ERR_SECTION_IO: c_uint = 0x22;
+pub const ERR_SECTION_OUT_OF_RANGE: c_uint = 0x23;
+
+// FFI errors
+pub const ERR_PANIC: c_uint = 0x24;
+
+// Container errors
+pub const ERR_INVALID_HANDLE: c_uint = 0x25;
+
+// Verification errors
+pub const ERR_SECTION_SIZE_MISMATCH: c_uint = 0x26;
+pub const ERR_SECTION_OUT_OF_BOUNDS: c_uint = 0x27;
+pub const ERR_CONTAINER_CORRUPTED: c_uint = 0x28;
+
+// IO backend errors
+pub const ERR_UNSUPPORTED_IO_VERSION: c_uint = 0x29;
+
+// JSON errors
+pub const ERR_SD_JSON_SYNTAX: c_uint = 0x2A;
+pub const ERR_SD_JSON_TYPE: c_uint = 0x2B;
+
+// Encoding errors
+pub const ERR_SD_MAX_DEPTH_EXCEEDED: c_uint = 0x2C;
+
 pub trait CErrCode
 {
     fn cerr_code(&self) -> u32;
 }
 
+//User defined error codes returned by ContainerIo callbacks are passed through unchanged
+fn user_error(e: &std::io::Error) -> Option<u32> {
+    e.get_ref().and_then(|v| v.downcast_ref::<UserError>()).map(|v| LastError::new(v.0, v.to_string()).set())
+}
+
 impl CErrCode for bpx::core::error::OpenError {
     fn cerr_code(&self) -> u32 {
         match self {
-            OpenError::SectionInUse => ERR_OPEN_SECTION_IN_USE,
-            OpenError::SectionNotLoaded => ERR_OPEN_SECTION_NOT_LOADED
+            OpenError::SectionInUse => LastError::new(ERR_OPEN_SECTION_IN_USE, "section in use").set(),
+            OpenError::SectionNotLoaded => LastError::new(ERR_OPEN_SECTION_NOT_LOADED, "section not loaded").set()
         }
     }
 }
@@ -88,11 +127,11 @@ impl CErrCode for bpx::core::error::OpenError {
 impl CErrCode for bpx::core::error::InflateError {
     fn cerr_code(&self) -> u32 {
         match self {
-            InflateError::Memory => ERR_INFLATE_MEMORY,
-            InflateError::Unsupported(_) => ERR_INFLATE_UNSUPPORTED,
-            InflateError::Data => ERR_INFLATE_DATA,
-            InflateError::Unknown => ERR_INFLATE_UNKNOWN,
-            InflateError::Io(_) => ERR_INFLATE_IO
+            InflateError::Memory => LastError::new(ERR_INFLATE_MEMORY, "inflate error: memory allocation failure").set(),
+            InflateError::Unsupported(v) => LastError::new(ERR_INFLATE_UNSUPPORTED, format!("inflate error: unsupported operation ({})", v)).set(),
+            InflateError::Data => LastError::new(ERR_INFLATE_DATA, "inflate error: data error").set(),
+            InflateError::Unknown => LastError::new(ERR_INFLATE_UNKNOWN, "inflate error: low-level unknown error").set(),
+            InflateError::Io(e) => user_error(e).unwrap_or_else(|| LastError::new(ERR_INFLATE_IO, format!("inflate error: io error: {}", e)).io(e).set())
         }
     }
 }
@@ -100,11 +139,11 @@ impl CErrCode for bpx::core::error::InflateError {
 impl CErrCode for bpx::core::error::DeflateError {
     fn cerr_code(&self) -> u32 {
         match self {
-            DeflateError::Memory => ERR_DEFLATE_MEMORY,
-            DeflateError::Unsupported(_) => ERR_DEFLATE_UNSUPPORTED,
-            DeflateError::Data => ERR_DEFLATE_DATA,
-            DeflateError::Unknown => ERR_DEFLATE_UNKNOWN,
-            DeflateError::Io(_) => ERR_DEFLATE_IO
+            DeflateError::Memory => LastError::new(ERR_DEFLATE_MEMORY, "deflate error: memory allocation failure").set(),
+            DeflateError::Unsupported(v) => LastError::new(ERR_DEFLATE_UNSUPPORTED, format!("deflate error: unsupported operation ({})", v)).set(),
+            DeflateError::Data => LastError::new(ERR_DEFLATE_DATA, "deflate error: data error").set(),
+            DeflateError::Unknown => LastError::new(ERR_DEFLATE_UNKNOWN, "deflate error: low-level unknown error").set(),
+            DeflateError::Io(e) => user_error(e).unwrap_or_else(|| LastError::new(ERR_DEFLATE_IO, format!("deflate error: io error: {}", e)).io(e).set())
         }
     }
 }
@@ -114,12 +153,13 @@ impl CErrCode for bpx::core::error::Error
     fn cerr_code(&self) -> u32
     {
         match self {
-            Error::Checksum {..} => ERR_CORE_CHKSUM,
-            Error::Io(_) => ERR_CORE_IO,
-            Error::BadVersion(_) => ERR_CORE_BAD_VERSION,
-            Error::BadSignature(_) => ERR_CORE_BAD_SIGNATURE,
+            Error::Checksum { expected, actual } => LastError::new(ERR_CORE_CHKSUM, format!("checksum mismatch: expected 0x{:08X}, got 0x{:08X}", expected, actual))
+                .checksum(*expected, *actual).set(),
+            Error::Io(e) => user_error(e).unwrap_or_else(|| LastError::new(ERR_CORE_IO, format!("io error: {}", e)).io(e).set()),
+            Error::BadVersion(v) => LastError::new(ERR_CORE_BAD_VERSION, format!("unknown file version ({})", v)).version(*v).set(),
+            Error::BadSignature(_) => LastError::new(ERR_CORE_BAD_SIGNATURE, "unknown file signature").set(),
             Error::Inflate(e) => e.cerr_code(),
-            Error::Capacity(_) => ERR_CORE_CAPACITY,
+            Error::Capacity(v) => LastError::new(ERR_CORE_CAPACITY, format!("maximum section size exceeded ({} > 2^32)", v)).capacity(*v).set(),
             Error::Deflate(e) => e.cerr_code(),
             Error::Open(e) => e.cerr_code()
         }
@@ -129,12 +169,28 @@ impl CErrCode for bpx::core::error::Error
 impl CErrCode for bpx::sd::error::Error {
     fn cerr_code(&self) -> u32 {
         match self {
-            bpx::sd::error::Error::Io(_) => ERR_SD_IO,
-            bpx::sd::error::Error::Truncation(_) => ERR_SD_TRUNCATION,
-            bpx::sd::error::Error::BadTypeCode(_) => ERR_SD_BAD_TYPE_CODE,
-            bpx::sd::error::Error::Utf8 => ERR_SD_UTF8,
-            bpx::sd::error::Error::CapacityExceeded(_) => ERR_SD_CAPACITY_EXCEEDED,
-            bpx::sd::error::Error::NotAnObject => ERR_SD_NOT_AN_OBJECT
+            bpx::sd::error::Error::Io(e) => LastError::new(ERR_SD_IO, format!("io error: {}", e)).io(e).set(),
+            bpx::sd::error::Error::Truncation(_) => LastError::new(ERR_SD_TRUNCATION, "structured data is truncated").set(),
+            bpx::sd::error::Error::BadTypeCode(v) => LastError::new(ERR_SD_BAD_TYPE_CODE, format!("unknown value type code ({})", v)).set(),
+            bpx::sd::error::Error::Utf8 => LastError::new(ERR_SD_UTF8, "utf8 error").set(),
+            bpx::sd::error::Error::CapacityExceeded(v) => LastError::new(ERR_SD_CAPACITY_EXCEEDED, format!("capacity exceeded ({} > 255)", v)).capacity(*v).set(),
+            bpx::sd::error::Error::NotAnObject => LastError::new(ERR_SD_NOT_AN_OBJECT, "not an object").set()
+        }
+    }
+}
+
+//Section IO errors, errors raised by BPX itself or by ContainerIo callbacks are passed through with their own codes
+impl CErrCode for std::io::Error {
+    fn cerr_code(&self) -> u32 {
+        if let Some(e) = self.get_ref().and_then(|v| v.downcast_ref::<Error>()) {
+            return e.cerr_code();
+        }
+        if let Some(code) = user_error(self) {
+            return code;
+        }
+        match self.kind() {
+            ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => LastError::new(ERR_SECTION_OUT_OF_RANGE, format!("out of range: {}", self)).io(self).set(),
+            _ => LastError::new(ERR_SECTION_IO, format!("io error: {}", self)).io(self).set()
         }
     }
 }
@@ -26,6 +26,12 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::any::Any;
+use std::os::raw::{c_int, c_uint};
+use std::panic::UnwindSafe;
+use crate::error_codes::ERR_PANIC;
+use crate::last_error::LastError;
+
 #[repr(transparent)]
 pub struct OutCell<T>(*mut T);
 
@@ -49,6 +55,78 @@ impl<T> Object<T> {
     }
 }
 
+//Value returned by an exported function when its body panics
+pub trait PanicDefault
+{
+    fn panic_default() -> Self;
+}
+
+impl PanicDefault for () {
+    fn panic_default() -> Self {}
+}
+
+impl PanicDefault for c_uint {
+    fn panic_default() -> Self {
+        ERR_PANIC
+    }
+}
+
+impl PanicDefault for c_int {
+    fn panic_default() -> Self {
+        -1
+    }
+}
+
+impl PanicDefault for usize {
+    fn panic_default() -> Self {
+        usize::MAX
+    }
+}
+
+impl PanicDefault for u64 {
+    fn panic_default() -> Self {
+        u64::MAX
+    }
+}
+
+impl PanicDefault for bool {
+    fn panic_default() -> Self {
+        false
+    }
+}
+
+impl<T> PanicDefault for *const T {
+    fn panic_default() -> Self {
+        std::ptr::null()
+    }
+}
+
+impl<T> PanicDefault for *mut T {
+    fn panic_default() -> Self {
+        std::ptr::null_mut()
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(v) = payload.downcast_ref::<&str>() {
+        v
+    } else if let Some(v) = payload.downcast_ref::<String>() {
+        v
+    } else {
+        "unknown panic"
+    }
+}
+
+pub fn catch_panic<R: PanicDefault, F: FnOnce() -> R + UnwindSafe>(f: F) -> R {
+    match std::panic::catch_unwind(f) {
+        Ok(v) => v,
+        Err(payload) => {
+            LastError::new(ERR_PANIC, format!("panic: {}", panic_message(&*payload))).set();
+            R::panic_default()
+        }
+    }
+}
+
 macro_rules! callback {
     (($($name: ident: $t: ty),*) $(-> $t1: ty)?) => {
         unsafe extern "C" fn ($($name: $t),*) $(-> $t1)?
@@ -65,7 +143,12 @@ macro_rules! export {
     ) => {
         $(
             #[no_mangle]
-            pub unsafe extern "C" fn $name ($($pname: $ptype),*) $(-> $ret)? $body
+            pub unsafe extern "C" fn $name ($($pname: $ptype),*) $(-> $ret)? {
+                crate::ffi_helper::catch_panic(std::panic::AssertUnwindSafe(|| {
+                    crate::last_error::LastError::clear();
+                    $body
+                }))
+            }
         )*
     };
 }
@@ -97,11 +180,12 @@ macro_rules! export_object {
                     $body
                 }
             )*
-            $(fn $closer_name(ptr: *mut *mut $obj) {
+            $(fn $closer_name(ptr: *mut *mut $obj) -> std::os::raw::c_uint {
                 let $closer_self = Box::from_raw(*ptr);
                 $closer_body
                 drop($closer_self); // deallocate the heap wrapper
                 std::ptr::write(ptr, std::ptr::null_mut()); // reset user pointer to NULL
+                crate::error_codes::ERR_NONE
             })?
         }
     };
@@ -26,8 +26,11 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::cell::Cell;
 use std::ffi::c_void;
+use std::fmt::{Display, Formatter};
 use std::io::{Error, ErrorKind, Read, Seek, Write};
+use std::rc::Rc;
 use std::os::raw::c_uint;
 use crate::error_codes::{ERR_CORE_IO, ERR_NONE};
 use crate::ffi_helper::callback;
@@ -51,10 +54,57 @@ pub struct ContainerIo
     pub flush: Option<callback!((userdata: *const c_void) -> c_uint)>
 }
 
+pub const CONTAINER_IO2_VERSION: u32 = 2;
+
+#[repr(C)]
+pub struct ContainerIo2
+{
+    //Must be CONTAINER_IO2_VERSION
+    pub version: u32,
+    pub userdata: *const c_void,
+    //Relative offsets are signed, unlike ContainerIo
+    pub seek: callback!((userdata: *const c_void, from: SeekFrom, offset: i64, new_pos: *mut u64) -> c_uint),
+    pub read: callback!((userdata: *const c_void, buffer: *mut u8, size: usize, bytes_read: *mut usize) -> c_uint),
+    pub write: Option<callback!((userdata: *const c_void, buffer: *const u8, size: usize, bytes_written: *mut usize) -> c_uint)>,
+    pub flush: Option<callback!((userdata: *const c_void) -> c_uint)>,
+    //When set, seeks from the end are resolved using the size and passed to seek as seeks from the start
+    pub size: Option<callback!((userdata: *const c_void, size: *mut u64) -> c_uint)>,
+    //Called once when the container is closed to release userdata
+    pub close: Option<callback!((userdata: *const c_void))>,
+    //Size of the read-ahead/write-behind buffer placed in front of the callbacks, 0 disables buffering
+    pub buffer_size: usize
+}
+
+//Carries a user defined error code returned by a callback through std::io::Error
+#[derive(Debug)]
+pub struct UserError(pub c_uint);
+
+impl Display for UserError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "low level C user defined custom error ({})", self.0)
+    }
+}
+
+impl std::error::Error for UserError {}
+
+enum SeekCallback
+{
+    Unsigned(callback!((userdata: *const c_void, from: SeekFrom, pos: u64, new_pos: *mut u64) -> c_uint)),
+    Signed(callback!((userdata: *const c_void, from: SeekFrom, offset: i64, new_pos: *mut u64) -> c_uint))
+}
+
 pub struct IoWrapper
 {
-    raw: ContainerIo,
-    last_error: c_uint
+    userdata: *const c_void,
+    seek: SeekCallback,
+    read: callback!((userdata: *const c_void, buffer: *mut u8, size: usize, bytes_read: *mut usize) -> c_uint),
+    write: Option<callback!((userdata: *const c_void, buffer: *const u8, size: usize, bytes_written: *mut usize) -> c_uint)>,
+    flush: Option<callback!((userdata: *const c_void) -> c_uint)>,
+    size: Option<callback!((userdata: *const c_void, size: *mut u64) -> c_uint)>,
+    close: Option<callback!((userdata: *const c_void))>,
+    last_error: Rc<Cell<c_uint>>
 }
 
 impl IoWrapper
@@ -62,11 +112,58 @@ impl IoWrapper
     pub fn new(raw: ContainerIo) -> IoWrapper
     {
         IoWrapper {
-            raw,
-            last_error: 0
+            userdata: raw.userdata,
+            seek: SeekCallback::Unsigned(raw.seek),
+            read: raw.read,
+            write: raw.write,
+            flush: raw.flush,
+            size: None,
+            close: None,
+            last_error: Rc::new(Cell::new(ERR_NONE))
+        }
+    }
+
+    pub fn new2(raw: ContainerIo2) -> IoWrapper
+    {
+        IoWrapper {
+            userdata: raw.userdata,
+            seek: SeekCallback::Signed(raw.seek),
+            read: raw.read,
+            write: raw.write,
+            flush: raw.flush,
+            size: raw.size,
+            close: raw.close,
+            last_error: Rc::new(Cell::new(ERR_NONE))
         }
     }
 
+    pub fn can_write(&self) -> bool
+    {
+        self.write.is_some()
+    }
+
+    //Whether seeks from the end call the size callback before seek
+    pub fn has_size(&self) -> bool
+    {
+        self.size.is_some()
+    }
+
+    //Shared with the container so that the last user defined error remains readable after the wrapper moved into it
+    pub fn last_error(&self) -> Rc<Cell<c_uint>>
+    {
+        self.last_error.clone()
+    }
+
+    pub fn size(&mut self) -> Option<std::io::Result<u64>>
+    {
+        let func = self.size?;
+        let mut size: u64 = 0;
+        let res = unsafe {
+            (func)(self.userdata, &mut size as _)
+        };
+        Some(self.handle_low_level_err(res, 0).map(|_| size))
+    }
+
     fn handle_low_level_err(&mut self, res: c_uint, count: usize) -> std::io::Result<usize>
     {
         if res == ERR_NONE {
@@ -74,8 +171,8 @@ impl IoWrapper
         } else if res == ERR_CORE_IO {
             Err(Error::last_os_error())
         } else {
-            self.last_error = res;
-            Err(Error::new(ErrorKind::Other, "Low level C user defined custom error"))
+            self.last_error.set(res);
+            Err(Error::new(ErrorKind::Other, UserError(res)))
         }
     }
 }
@@ -86,7 +183,7 @@ impl Read for IoWrapper
     {
         let mut bytes_read = 0;
         let res = unsafe {
-            (self.raw.read)(self.raw.userdata, buf.as_mut_ptr(), buf.len(), &mut bytes_read as _)
+            (self.read)(self.userdata, buf.as_mut_ptr(), buf.len(), &mut bytes_read as _)
         };
         self.handle_low_level_err(res, bytes_read)
     }
@@ -97,18 +194,18 @@ impl Write for IoWrapper
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
     {
         let mut bytes_written = 0;
-        let func = self.raw.write.ok_or_else(|| Error::new(ErrorKind::Unsupported, "Write operation is unsupported"))?;
+        let func = self.write.ok_or_else(|| Error::new(ErrorKind::Unsupported, "Write operation is unsupported"))?;
         let res = unsafe {
-            (func)(self.raw.userdata, buf.as_ptr(), buf.len(), &mut bytes_written as _)
+            (func)(self.userdata, buf.as_ptr(), buf.len(), &mut bytes_written as _)
         };
         self.handle_low_level_err(res, bytes_written)
     }
 
     fn flush(&mut self) -> std::io::Result<()>
     {
-        let func = self.raw.flush.ok_or_else(|| Error::new(ErrorKind::Unsupported, "Flush operation is unsupported"))?;
+        let func = self.flush.ok_or_else(|| Error::new(ErrorKind::Unsupported, "Flush operation is unsupported"))?;
         let res = unsafe {
-            (func)(self.raw.userdata)
+            (func)(self.userdata)
         };
         self.handle_low_level_err(res, 0).map(|_| ())
     }
@@ -118,14 +215,49 @@ impl Seek for IoWrapper
 {
     fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64>
     {
+        //The size callback is only needed to resolve seeks from the end
+        let pos = match pos {
+            std::io::SeekFrom::End(offset) => match self.size() {
+                Some(size) => {
+                    let pos = size?.checked_add_signed(offset)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+                    std::io::SeekFrom::Start(pos)
+                },
+                None => pos
+            },
+            pos => pos
+        };
         unsafe {
             let mut new_pos: u64 = 0; //Rust is buggy and can't figure out that the function only accepts u64 whereas it can with usize!!
-            let res = match pos {
-                std::io::SeekFrom::Start(offset) => (self.raw.seek)(self.raw.userdata, SeekFrom::Start, offset, &mut new_pos as _),
-                std::io::SeekFrom::End(offset) => (self.raw.seek)(self.raw.userdata, SeekFrom::End, offset as u64, &mut new_pos as _),
-                std::io::SeekFrom::Current(offset) => (self.raw.seek)(self.raw.userdata, SeekFrom::Current, offset as u64, &mut new_pos as _)
+            let res = match self.seek {
+                //Legacy ContainerIo: negative relative offsets are passed as their two's complement
+                SeekCallback::Unsigned(func) => match pos {
+                    std::io::SeekFrom::Start(offset) => (func)(self.userdata, SeekFrom::Start, offset, &mut new_pos as _),
+                    std::io::SeekFrom::End(offset) => (func)(self.userdata, SeekFrom::End, offset as u64, &mut new_pos as _),
+                    std::io::SeekFrom::Current(offset) => (func)(self.userdata, SeekFrom::Current, offset as u64, &mut new_pos as _)
+                },
+                SeekCallback::Signed(func) => match pos {
+                    std::io::SeekFrom::Start(offset) => {
+                        let offset = i64::try_from(offset).map_err(|_| Error::new(ErrorKind::InvalidInput, "Seek position is too large"))?;
+                        (func)(self.userdata, SeekFrom::Start, offset, &mut new_pos as _)
+                    },
+                    std::io::SeekFrom::End(offset) => (func)(self.userdata, SeekFrom::End, offset, &mut new_pos as _),
+                    std::io::SeekFrom::Current(offset) => (func)(self.userdata, SeekFrom::Current, offset, &mut new_pos as _)
+                }
             };
             self.handle_low_level_err(res, 0).map(|_| new_pos)
         }
     }
 }
+
+impl Drop for IoWrapper
+{
+    fn drop(&mut self)
+    {
+        if let Some(func) = self.close {
+            unsafe {
+                (func)(self.userdata)
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_uint};
+use crate::ffi_helper::catch_panic;
+use crate::ffi_helper::OutCell;
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct ErrorInfo
+{
+    pub code: c_uint,
+    //Raw OS error code of the underlying io error, 0 if none
+    pub os_error: c_int,
+    pub expected_checksum: u32,
+    pub actual_checksum: u32,
+    pub version: u32,
+    pub capacity: u64,
+    pub message: *const c_char
+}
+
+pub struct LastError
+{
+    info: ErrorInfo,
+    message: CString
+}
+
+impl LastError
+{
+    pub fn new(code: c_uint, message: impl Into<Vec<u8>>) -> LastError
+    {
+        LastError {
+            info: ErrorInfo {
+                code,
+                os_error: 0,
+                expected_checksum: 0,
+                actual_checksum: 0,
+                version: 0,
+                capacity: 0,
+                message: std::ptr::null()
+            },
+            message: CString::new(message).unwrap_or_default()
+        }
+    }
+
+    pub fn io(mut self, e: &std::io::Error) -> Self
+    {
+        self.info.os_error = e.raw_os_error().unwrap_or(0);
+        self
+    }
+
+    pub fn checksum(mut self, expected: u32, actual: u32) -> Self
+    {
+        self.info.expected_checksum = expected;
+        self.info.actual_checksum = actual;
+        self
+    }
+
+    pub fn version(mut self, version: u32) -> Self
+    {
+        self.info.version = version;
+        self
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self
+    {
+        self.info.capacity = capacity as u64;
+        self
+    }
+
+    //Stores this error as the last error of the current thread and returns its code
+    pub fn set(mut self) -> c_uint
+    {
+        let code = self.info.code;
+        self.info.message = self.message.as_ptr();
+        LAST_ERROR.with(|v| *v.borrow_mut() = Some(self));
+        code
+    }
+
+    //Called by every exported function when it starts so that the last error always belongs to the latest call
+    pub fn clear()
+    {
+        LAST_ERROR.with(|v| *v.borrow_mut() = None);
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = RefCell::new(None);
+}
+
+//Not declared through export! which would clear the error they are reading
+#[no_mangle]
+pub unsafe extern "C" fn bpx_get_last_error_message() -> *const c_char
+{
+    catch_panic(|| LAST_ERROR.with(|v| v.borrow().as_ref().map(|v| v.info.message).unwrap_or(std::ptr::null())))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn bpx_get_last_error(info: OutCell<ErrorInfo>) -> bool
+{
+    catch_panic(std::panic::AssertUnwindSafe(|| match LAST_ERROR.with(|v| v.borrow().as_ref().map(|v| v.info.clone())) {
+        Some(v) => {
+            info.set(v);
+            true
+        },
+        None => false
+    }))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::mem::MaybeUninit;
+    use crate::error_codes::{ERR_CORE_IO, ERR_NONE};
+    use crate::verify::{bpx_verify_report_free, VerifyReport};
+    use super::*;
+
+    unsafe fn last_error() -> Option<ErrorInfo>
+    {
+        let mut info = MaybeUninit::<ErrorInfo>::uninit();
+        match bpx_get_last_error(std::mem::transmute(info.as_mut_ptr())) {
+            true => Some(info.assume_init()),
+            false => None
+        }
+    }
+
+    #[test]
+    fn cleared_by_next_call()
+    {
+        unsafe {
+            LastError::new(ERR_CORE_IO, "test").set();
+            //Reading the error does not clear it
+            assert_eq!(last_error().map(|v| v.code), Some(ERR_CORE_IO));
+            assert!(!bpx_get_last_error_message().is_null());
+            assert_eq!(last_error().map(|v| v.code), Some(ERR_CORE_IO));
+            let mut report = VerifyReport::new(ERR_NONE, Vec::new());
+            bpx_verify_report_free(&mut report);
+            assert!(last_error().is_none());
+            assert!(bpx_get_last_error_message().is_null());
+        }
+    }
+}
@@ -28,13 +28,25 @@
 
 pub mod container;
 pub mod section;
+pub mod verify;
+pub mod repack;
 
 mod path_utils;
 mod error_codes;
+mod last_error;
 mod types;
 mod open;
 mod io_wrapper;
+mod buffered_io;
+mod memory_io;
+mod mmap_io;
+mod c_file_io;
 mod container_wrapper;
+mod raw_section;
 mod sd;
+#[cfg(target_os = "linux")]
+mod section_file;
 mod ffi_helper;
 mod utils;
+#[cfg(test)]
+mod test_utils;
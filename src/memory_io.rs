@@ -0,0 +1,100 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+pub enum MemoryIo
+{
+    Borrowed(Cursor<&'static [u8]>),
+    Owned(Cursor<Vec<u8>>)
+}
+
+impl MemoryIo
+{
+    //SAFETY: the buffer must outlive the container it is attached to.
+    pub unsafe fn borrowed(buffer: *const u8, size: usize) -> MemoryIo
+    {
+        let slice = if size == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(buffer, size)
+        };
+        MemoryIo::Borrowed(Cursor::new(slice))
+    }
+
+    pub fn owned() -> MemoryIo
+    {
+        MemoryIo::Owned(Cursor::new(Vec::new()))
+    }
+
+    pub fn into_vec(self) -> Option<Vec<u8>>
+    {
+        match self {
+            MemoryIo::Borrowed(_) => None,
+            MemoryIo::Owned(v) => Some(v.into_inner())
+        }
+    }
+}
+
+impl Read for MemoryIo
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        match self {
+            MemoryIo::Borrowed(v) => v.read(buf),
+            MemoryIo::Owned(v) => v.read(buf)
+        }
+    }
+}
+
+impl Write for MemoryIo
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        match self {
+            MemoryIo::Borrowed(_) => Err(Error::new(ErrorKind::Unsupported, "Cannot write into a borrowed buffer")),
+            MemoryIo::Owned(v) => v.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryIo
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64>
+    {
+        match self {
+            MemoryIo::Borrowed(v) => v.seek(pos),
+            MemoryIo::Owned(v) => v.seek(pos)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+pub struct Mapping
+{
+    ptr: *mut u8,
+    len: usize
+}
+
+impl Mapping
+{
+    #[cfg(unix)]
+    pub fn new(file: &File) -> std::io::Result<Mapping>
+    {
+        use std::os::unix::io::AsRawFd;
+        let len = file.metadata()?.len() as usize;
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_: &File) -> std::io::Result<Mapping>
+    {
+        Err(Error::new(ErrorKind::Unsupported, "Memory mapping is unsupported on this platform"))
+    }
+
+    pub fn as_slice(&self) -> &[u8]
+    {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mapping
+{
+    fn drop(&mut self)
+    {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as _, self.len);
+        }
+    }
+}
+
+pub struct MmapIo
+{
+    mapping: Rc<Mapping>,
+    pos: u64
+}
+
+impl MmapIo
+{
+    pub fn new(mapping: Rc<Mapping>) -> MmapIo
+    {
+        MmapIo {
+            mapping,
+            pos: 0
+        }
+    }
+}
+
+impl Read for MmapIo
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+    {
+        let data = self.mapping.as_slice();
+        let start = std::cmp::min(self.pos, data.len() as u64) as usize;
+        let len = std::cmp::min(buf.len(), data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for MmapIo
+{
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize>
+    {
+        Err(Error::new(ErrorKind::Unsupported, "Cannot write into a memory mapped file"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+impl Seek for MmapIo
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64>
+    {
+        let (base, offset) = match pos {
+            SeekFrom::Start(v) => {
+                self.pos = v;
+                return Ok(v);
+            },
+            SeekFrom::End(v) => (self.mapping.len as u64, v),
+            SeekFrom::Current(v) => (self.pos, v)
+        };
+        match base.checked_add_signed(offset) {
+            Some(v) => {
+                self.pos = v;
+                Ok(v)
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))
+        }
+    }
+}
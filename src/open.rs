@@ -26,7 +26,7 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::os::raw::{c_char, c_uint};
+use std::os::raw::{c_char, c_int, c_uint};
 use bpx::core::builder::MainHeaderBuilder;
 use std::fs::File;
 use std::ffi::CStr;
@@ -36,13 +36,22 @@ use crate::error_codes::ERR_FILE_OPEN;
 use crate::path_utils::cstr_to_path;
 use crate::types::Container;
 use crate::error_codes::ERR_NONE;
+use crate::error_codes::ERR_UNSUPPORTED_IO_VERSION;
 use crate::error_codes::unwrap_or_err;
 use crate::ffi_helper::export;
 use crate::ffi_helper::Object;
 use crate::ffi_helper::OutCell;
-use crate::container_wrapper::ContainerWrapper;
+use crate::container_wrapper::{ContainerWrapper, SharedWrapper};
+use crate::buffered_io::BufferedIo;
 use crate::io_wrapper::ContainerIo;
+use crate::io_wrapper::ContainerIo2;
+use crate::io_wrapper::CONTAINER_IO2_VERSION;
 use crate::io_wrapper::IoWrapper;
+use crate::last_error::LastError;
+use crate::memory_io::MemoryIo;
+use crate::c_file_io::CFileIo;
+use crate::mmap_io::{Mapping, MmapIo};
+use std::rc::Rc;
 
 #[repr(C)]
 pub struct ContainerOptions
@@ -52,46 +61,219 @@ pub struct ContainerOptions
     pub type_ext: [u8; 16]
 }
 
+#[repr(C)]
+pub enum OpenMode
+{
+    ReadOnly,
+    ReadWrite,
+    Create
+}
+
+#[repr(C)]
+pub struct OpenOptions
+{
+    pub mode: OpenMode,
+    //Only used by OpenMode::Create when the file does not exist yet, may be NULL otherwise
+    pub header: *const ContainerOptions,
+    //Only used by OpenMode::ReadOnly, falls back to regular reads when the file cannot be mapped
+    pub mmap: bool
+}
+
+pub fn file_error(code: c_uint, e: std::io::Error) -> c_uint
+{
+    LastError::new(code, format!("io error: {}", e)).io(&e).set()
+}
+
+//Builds the backend described by io, then attaches its error and statistics cells to the container built by f
+fn with_io2(io: ContainerIo2, f: impl FnOnce(ContainerWrapper, bool) -> Result<Container, c_uint>) -> Result<Container, c_uint>
+{
+    if io.version != CONTAINER_IO2_VERSION {
+        return Err(LastError::new(ERR_UNSUPPORTED_IO_VERSION, format!("unsupported io backend version ({})", io.version))
+            .version(io.version).set());
+    }
+    let buffer_size = io.buffer_size;
+    let io = IoWrapper::new2(io);
+    //A backend without a write callback can only ever be read
+    let read_only = !io.can_write();
+    let io_error = io.last_error();
+    if buffer_size == 0 {
+        return f(ContainerWrapper::from(io), read_only).map(|v| v.with_io_error(io_error));
+    }
+    let io = BufferedIo::new(io, buffer_size);
+    let io_stats = io.stats();
+    f(ContainerWrapper::from(io), read_only).map(|v| v.with_io_error(io_error).with_io_stats(io_stats))
+}
+
+pub fn open_container(wrapper: ContainerWrapper, read_only: bool) -> Result<Container, c_uint>
+{
+    let backend = SharedWrapper::new(wrapper);
+    let container = bpx::core::Container::open(backend.clone()).map_err(|e| e.cerr_code())?;
+    Ok(Container::new(container, backend, read_only))
+}
+
+pub unsafe fn create_container(wrapper: ContainerWrapper, header: *const ContainerOptions) -> Container
+{
+    let h = &*header;
+    let backend = SharedWrapper::new(wrapper);
+    let container = bpx::core::Container::create(backend.clone(), MainHeaderBuilder::new()
+        .ty(h.ty)
+        .type_ext(h.type_ext)
+        .version(h.version));
+    Container::new(container, backend, false).created()
+}
+
 export!
 {
     fn bpx_container_open(file: *const c_char, out: OutCell<Object<Container>>) -> c_uint
     {
         let path = unwrap_or_err!(cstr_to_path(CStr::from_ptr(file)));
-        let f = unwrap_or_err!(File::options().read(true).write(true).open(path).map_err(|_| ERR_FILE_OPEN));
-        let container = unwrap_or_err!(bpx::core::Container::open(ContainerWrapper::from(f)).map_err(|e| e.cerr_code()));
+        let f = unwrap_or_err!(File::options().read(true).write(true).open(path).map_err(|e| file_error(ERR_FILE_OPEN, e)));
+        let container = unwrap_or_err!(open_container(ContainerWrapper::from(f), false));
         out.set(Object::new(container));
         ERR_NONE
     }
 
-    fn bpx_container_create(file: *const c_char, header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
+    fn bpx_container_open_with_options(file: *const c_char, options: *const OpenOptions, out: OutCell<Object<Container>>) -> c_uint
     {
         let path = unwrap_or_err!(cstr_to_path(CStr::from_ptr(file)));
-        let f = unwrap_or_err!(File::create(path).map_err(|_| ERR_FILE_CREATE));
-        let h = &*header;
-        let container = bpx::core::Container::create(ContainerWrapper::from(f), MainHeaderBuilder::new()
-            .ty(h.ty)
-            .type_ext(h.type_ext)
-            .version(h.version));
+        let options = &*options;
+        let (f, read_only) = match options.mode {
+            OpenMode::ReadOnly => (File::options().read(true).open(path), true),
+            OpenMode::ReadWrite => (File::options().read(true).write(true).open(path), false),
+            OpenMode::Create => (File::options().read(true).write(true).create(true).open(path), false)
+        };
+        let f = unwrap_or_err!(f.map_err(|e| file_error(ERR_FILE_OPEN, e)));
+        if let OpenMode::Create = options.mode {
+            let len = unwrap_or_err!(f.metadata().map(|v| v.len()).map_err(|e| file_error(ERR_FILE_CREATE, e)));
+            if len == 0 {
+                if options.header.is_null() {
+                    return LastError::new(ERR_FILE_CREATE, "missing container header to create a new file").set();
+                }
+                out.set(Object::new(create_container(ContainerWrapper::from(f), options.header)));
+                return ERR_NONE;
+            }
+        }
+        if read_only && options.mmap {
+            if let Ok(mapping) = Mapping::new(&f).map(Rc::new) {
+                let wrapper = ContainerWrapper::from(MmapIo::new(mapping.clone()));
+                let container = unwrap_or_err!(open_container(wrapper, true));
+                out.set(Object::new(container.with_mapping(mapping)));
+                return ERR_NONE;
+            }
+        }
+        let container = unwrap_or_err!(open_container(ContainerWrapper::from(f), read_only));
         out.set(Object::new(container));
         ERR_NONE
     }
 
+    fn bpx_container_create(file: *const c_char, header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let path = unwrap_or_err!(cstr_to_path(CStr::from_ptr(file)));
+        let f = unwrap_or_err!(File::create(path).map_err(|e| file_error(ERR_FILE_CREATE, e)));
+        out.set(Object::new(create_container(ContainerWrapper::from(f), header)));
+        ERR_NONE
+    }
+
     fn bpx_container_open2(io: ContainerIo, out: OutCell<Object<Container>>) -> c_uint
     {
-        let wrapper = ContainerWrapper::from(IoWrapper::new(io));
-        let container = unwrap_or_err!(bpx::core::Container::open(wrapper).map_err(|e| e.cerr_code()));
-        out.set(Object::new(container));
+        //A backend without a write callback can only ever be read
+        let io = IoWrapper::new(io);
+        let read_only = !io.can_write();
+        let io_error = io.last_error();
+        let wrapper = ContainerWrapper::from(io);
+        let container = unwrap_or_err!(open_container(wrapper, read_only));
+        out.set(Object::new(container.with_io_error(io_error)));
         ERR_NONE
     }
 
     fn bpx_container_create2(io: ContainerIo, header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
     {
-        let h = &*header;
-        let wrapper = ContainerWrapper::from(IoWrapper::new(io));
-        let container = bpx::core::Container::create(wrapper, MainHeaderBuilder::new()
-            .ty(h.ty)
-            .type_ext(h.type_ext)
-            .version(h.version));
+        let io = IoWrapper::new(io);
+        let io_error = io.last_error();
+        out.set(Object::new(create_container(ContainerWrapper::from(io), header).with_io_error(io_error)));
+        ERR_NONE
+    }
+
+    //Ownership of userdata is taken once the version check passes: close is then called even if opening fails.
+    fn bpx_container_open_io2(io: ContainerIo2, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let container = unwrap_or_err!(with_io2(io, |wrapper, read_only| {
+            open_container(wrapper, read_only)
+        }));
+        out.set(Object::new(container));
+        ERR_NONE
+    }
+
+    fn bpx_container_create_io2(io: ContainerIo2, header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let container = unwrap_or_err!(with_io2(io, |wrapper, _| Ok(create_container(wrapper, header))));
+        out.set(Object::new(container));
+        ERR_NONE
+    }
+
+    fn bpx_container_open_memory(buffer: *const u8, size: usize, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let wrapper = ContainerWrapper::from(MemoryIo::borrowed(buffer, size));
+        let container = unwrap_or_err!(open_container(wrapper, true));
+        out.set(Object::new(container));
+        ERR_NONE
+    }
+
+    fn bpx_container_create_memory(header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let wrapper = ContainerWrapper::from(MemoryIo::owned());
+        out.set(Object::new(create_container(wrapper, header)));
+        ERR_NONE
+    }
+}
+
+//When take_ownership is false the descriptor is duplicated: it stays open after the container is closed but shares
+// its file offset with the container.
+#[cfg(unix)]
+unsafe fn fd_to_file(fd: c_int, take_ownership: bool) -> Result<File, c_uint>
+{
+    use std::os::unix::io::FromRawFd;
+    let fd = match take_ownership {
+        true => fd,
+        false => libc::dup(fd)
+    };
+    if fd == -1 {
+        return Err(file_error(ERR_FILE_OPEN, std::io::Error::last_os_error()));
+    }
+    Ok(File::from_raw_fd(fd))
+}
+
+#[cfg(unix)]
+export!
+{
+    fn bpx_container_open_fd(fd: c_int, take_ownership: bool, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 {
+            return file_error(ERR_FILE_OPEN, std::io::Error::last_os_error());
+        }
+        let read_only = flags & libc::O_ACCMODE == libc::O_RDONLY;
+        let f = unwrap_or_err!(fd_to_file(fd, take_ownership));
+        let container = unwrap_or_err!(open_container(ContainerWrapper::from(f), read_only));
+        out.set(Object::new(container));
+        ERR_NONE
+    }
+
+    fn bpx_container_create_fd(fd: c_int, take_ownership: bool, header: *const ContainerOptions, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let f = unwrap_or_err!(fd_to_file(fd, take_ownership));
+        out.set(Object::new(create_container(ContainerWrapper::from(f), header)));
+        ERR_NONE
+    }
+}
+
+export!
+{
+    fn bpx_container_open_file(file: *mut libc::FILE, take_ownership: bool, out: OutCell<Object<Container>>) -> c_uint
+    {
+        let io = CFileIo::new(file, take_ownership);
+        let read_only = io.is_read_only();
+        let container = unwrap_or_err!(open_container(ContainerWrapper::from(io), read_only));
         out.set(Object::new(container));
         ERR_NONE
     }
@@ -38,6 +38,8 @@ pub fn cstr_to_path(cstr: &CStr) -> Result<&Path, u32>
         }
     #[cfg(windows)]
         {
-            cstr.to_str().map_err(|_| ERR_INVALID_PATH).map(|v| Path::new(v))
+            use crate::error_codes::ERR_INVALID_PATH;
+            use crate::last_error::LastError;
+            cstr.to_str().map_err(|_| LastError::new(ERR_INVALID_PATH, "path is not valid UTF-8").set()).map(|v| Path::new(v))
         }
 }
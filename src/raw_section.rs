@@ -0,0 +1,233 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//A raw section is a copy of a compressed section which reuses its stored payload instead of decompressing and
+// compressing it again. BPX has no way to write such a payload: the section is handed to BPX as an uncompressed
+// section holding the payload, its original header is written back once BPX is done saving, then the container is
+// reloaded from its backend so that BPX reads the section as the compressed section it now is. Reading the section
+// before it is saved decompresses the payload in place, the next save then compresses it again like any other section.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::os::raw::c_uint;
+use bpx::core::header::{SectionHeader, SIZE_MAIN_HEADER, SIZE_SECTION_HEADER};
+use bpx::core::SectionData;
+use crate::container::{copy_options, section_header_builder, COMPRESSION_XZ, COMPRESSION_ZLIB};
+use crate::error_codes::{CErrCode, ERR_CONTAINER_CORRUPTED};
+use crate::last_error::LastError;
+use crate::types::Container;
+
+#[derive(Copy, Clone)]
+pub enum RawSection
+{
+    //The section holds the stored payload, which save writes as is
+    Stored(SectionHeader),
+    //The section holds the decompressed payload, which save compresses again
+    Decoded(SectionHeader)
+}
+
+impl RawSection
+{
+    pub fn header(&self) -> &SectionHeader
+    {
+        match self {
+            RawSection::Stored(v) | RawSection::Decoded(v) => v
+        }
+    }
+}
+
+//Errors of the backend itself are reported like the ones BPX runs into
+pub fn backend_error(e: std::io::Error) -> c_uint
+{
+    bpx::core::error::Error::Io(e).cerr_code()
+}
+
+fn read_section(container: &Container, handle: bpx::core::Handle, capacity: usize) -> Result<Vec<u8>, c_uint>
+{
+    let mut data = container.sections().load(handle).map_err(|e| e.cerr_code())?;
+    let mut buffer = Vec::with_capacity(capacity);
+    data.seek(SeekFrom::Start(0)).and_then(|_| data.read_to_end(&mut buffer)).map_err(|e| e.cerr_code())?;
+    Ok(buffer)
+}
+
+//Returns the header and the stored payload of a compressed section, None if the backend holds no up to date payload
+pub fn read_payload(container: &Container, handle: bpx::core::Handle) -> Result<Option<(SectionHeader, Vec<u8>)>, c_uint>
+{
+    match container.raw_section(handle) {
+        Some(RawSection::Stored(header)) => return Ok(Some((header, read_section(container, handle, header.csize as usize)?))),
+        Some(RawSection::Decoded(_)) => return Ok(None),
+        None => ()
+    }
+    let header = *container.sections().header(handle);
+    if header.flags & (COMPRESSION_ZLIB | COMPRESSION_XZ) == 0 || container.is_modified(handle) {
+        return Ok(None);
+    }
+    Ok(Some((header, read_stored(container, &header)?)))
+}
+
+//Reads the payload of a section from the backend, whether the section is loaded or not
+pub fn read_stored(container: &Container, header: &SectionHeader) -> Result<Vec<u8>, c_uint>
+{
+    let mut payload = vec![0; header.csize as usize];
+    let mut backend = container.backend();
+    backend.seek(SeekFrom::Start(header.pointer)).and_then(|_| backend.read_exact(&mut payload)).map_err(backend_error)?;
+    Ok(payload)
+}
+
+//Checksum of the main header: every byte except the checksum itself and every byte of the section headers
+pub fn main_header_checksum(main_header: &[u8], headers: &[u8]) -> u32
+{
+    main_header[..4].iter().chain(&main_header[8..]).chain(headers).fold(0u32, |acc, v| acc.wrapping_add(*v as u32))
+}
+
+//Reads the main header and the section header table as currently stored in the backend
+pub fn read_headers(container: &Container) -> Result<([u8; SIZE_MAIN_HEADER], Vec<u8>), c_uint>
+{
+    let mut backend = container.backend();
+    let mut main_header = [0; SIZE_MAIN_HEADER];
+    backend.seek(SeekFrom::Start(0)).and_then(|_| backend.read_exact(&mut main_header)).map_err(backend_error)?;
+    let section_num = u32::from_le_bytes([main_header[16], main_header[17], main_header[18], main_header[19]]) as usize;
+    let mut headers = vec![0; section_num * SIZE_SECTION_HEADER];
+    backend.read_exact(&mut headers).map_err(backend_error)?;
+    Ok((main_header, headers))
+}
+
+fn write_header(header: &SectionHeader, out: &mut [u8])
+{
+    out[0..8].copy_from_slice(&header.pointer.to_le_bytes());
+    out[8..12].copy_from_slice(&header.csize.to_le_bytes());
+    out[12..16].copy_from_slice(&header.size.to_le_bytes());
+    out[16..20].copy_from_slice(&header.chksum.to_le_bytes());
+    out[20] = header.ty;
+    out[21] = header.flags;
+}
+
+//Decompresses (and checks) a payload by handing it to BPX as the only section of a container built in memory
+pub fn decode_payload(header: &SectionHeader, payload: &[u8], version: u32) -> Result<Vec<u8>, c_uint>
+{
+    let pointer = SIZE_MAIN_HEADER + SIZE_SECTION_HEADER;
+    let mut headers = [0; SIZE_SECTION_HEADER];
+    write_header(&SectionHeader {
+        pointer: pointer as u64,
+        ..*header
+    }, &mut headers);
+    let mut main_header = [0; SIZE_MAIN_HEADER];
+    main_header[0..3].copy_from_slice(b"BPX");
+    main_header[8..16].copy_from_slice(&((pointer + payload.len()) as u64).to_le_bytes());
+    main_header[16..20].copy_from_slice(&1u32.to_le_bytes());
+    main_header[20..24].copy_from_slice(&version.to_le_bytes());
+    let chksum = main_header_checksum(&main_header, &headers);
+    main_header[4..8].copy_from_slice(&chksum.to_le_bytes());
+    let scratch = bpx::core::Container::open(Cursor::new([&main_header[..], &headers, payload].concat()))
+        .map_err(|e| e.cerr_code())?;
+    let content = match scratch.sections().find_by_index(0) {
+        Some(v) => {
+            let mut data = scratch.sections().load(v).map_err(|e| e.cerr_code())?;
+            let mut content = Vec::with_capacity(header.size as usize);
+            data.seek(SeekFrom::Start(0)).and_then(|_| data.read_to_end(&mut content)).map_err(|e| e.cerr_code())?;
+            content
+        },
+        None => Vec::new()
+    };
+    Ok(content)
+}
+
+//Replaces the payload held by a stored raw section with its content so that it can be read like any other section
+pub fn decode(container: &Container, handle: bpx::core::Handle) -> Result<(), c_uint>
+{
+    let header = match container.raw_section(handle) {
+        Some(RawSection::Stored(v)) => v,
+        _ => return Ok(())
+    };
+    let payload = read_section(container, handle, header.csize as usize)?;
+    let content = decode_payload(&header, &payload, container.get_main_header().version)?;
+    {
+        let mut data = container.sections().load(handle).map_err(|e| e.cerr_code())?;
+        data.seek(SeekFrom::Start(0)).and_then(|_| data.write_all(&content)).map_err(|e| e.cerr_code())?;
+        let extra = data.size() - content.len();
+        if extra > 0 {
+            data.truncate(extra).map_err(|e| e.cerr_code())?;
+        }
+        data.seek(SeekFrom::Start(0)).map_err(|e| e.cerr_code())?;
+    }
+    container.set_raw_section(handle, RawSection::Decoded(header));
+    Ok(())
+}
+
+//Moves the content of decoded raw sections into sections created with their original flags, which BPX then compresses
+pub fn encode_decoded(container: &mut Container) -> Result<(), c_uint>
+{
+    for (handle, raw) in container.raw_sections() {
+        if let RawSection::Decoded(header) = raw {
+            let new_handle = container.create_section(section_header_builder(&copy_options(&header)));
+            let res = container.sections().load(handle).map_err(|e| e.cerr_code()).and_then(|mut data| {
+                let mut section = container.sections().open(new_handle).map_err(|e| e.cerr_code())?;
+                data.seek(SeekFrom::Start(0)).and_then(|_| std::io::copy(&mut *data, &mut *section)).map_err(|e| e.cerr_code())
+            });
+            if let Err(e) = res {
+                container.remove_section(new_handle);
+                return Err(e);
+            }
+            container.replace_section(handle, new_handle);
+        }
+    }
+    Ok(())
+}
+
+//Writes the original headers of the stored raw sections over the ones written by BPX and updates the main header
+// checksum, returns false if there was nothing to write
+pub fn patch_headers(container: &Container) -> Result<bool, c_uint>
+{
+    let raw_sections = container.raw_sections();
+    if raw_sections.is_empty() {
+        return Ok(false);
+    }
+    let (mut main_header, mut headers) = read_headers(container)?;
+    //BPX writes the section header table in the order of its section table
+    let indices: HashMap<u32, usize> = container.sections().iter().enumerate().map(|(i, v)| (v.into_raw(), i)).collect();
+    for (handle, raw) in raw_sections {
+        let stored = container.sections().header(handle);
+        let header = headers.chunks_exact_mut(SIZE_SECTION_HEADER).nth(indices[&handle.into_raw()]);
+        match header {
+            Some(v) if v[0..8] == stored.pointer.to_le_bytes() => write_header(&SectionHeader {
+                pointer: stored.pointer,
+                ..*raw.header()
+            }, v),
+            _ => return Err(LastError::new(ERR_CONTAINER_CORRUPTED, format!("section {} is not where BPX was expected to write it",
+                container.export_handle(handle))).set())
+        }
+    }
+    let chksum = main_header_checksum(&main_header, &headers);
+    main_header[4..8].copy_from_slice(&chksum.to_le_bytes());
+    let mut backend = container.backend();
+    backend.seek(SeekFrom::Start(0))
+        .and_then(|_| backend.write_all(&main_header))
+        .and_then(|_| backend.write_all(&headers))
+        .map_err(backend_error)?;
+    Ok(true)
+}
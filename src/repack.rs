@@ -0,0 +1,149 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io::{Seek, SeekFrom};
+use std::os::raw::c_uint;
+use crate::container::{copy_options, rewrite};
+use crate::container_wrapper::ContainerWrapper;
+use crate::error_codes::ERR_NONE;
+use crate::error_codes::unwrap_or_err;
+use crate::ffi_helper::export;
+use crate::ffi_helper::export_object;
+use crate::ffi_helper::OutCell;
+use crate::io_wrapper::{ContainerIo, IoWrapper};
+use crate::raw_section::backend_error;
+use crate::types::{Container, Handle};
+
+#[repr(C)]
+pub struct RepackSectionOptions
+{
+    pub handle: Handle,
+    pub flags: u8,
+    pub threshold: u32
+}
+
+#[repr(C)]
+pub struct RepackOptions
+{
+    //Applies flags and threshold to every section not listed in sections, otherwise those sections keep their flags
+    pub override_all: bool,
+    pub flags: u8,
+    pub threshold: u32,
+    pub sections: *const RepackSectionOptions,
+    pub section_count: usize
+}
+
+//The main header of src only reflects its last save, the backend holds what is actually stored
+pub fn bytes_saved_by(src: &Container, dst: &Container) -> Result<i64, c_uint>
+{
+    let size = src.backend().seek(SeekFrom::End(0)).map_err(backend_error)?;
+    Ok(size as i64 - dst.get_main_header().file_size as i64)
+}
+
+export_object! {
+    Container {
+        //options may be NULL to keep the flags of every section
+        fn bpx_container_repack(this, io: ContainerIo, options: *const RepackOptions, bytes_saved: OutCell<i64>) -> c_uint {
+            let options = options.as_ref();
+            let sections = match options {
+                Some(v) if v.section_count > 0 => std::slice::from_raw_parts(v.sections, v.section_count),
+                _ => &[]
+            };
+            for v in sections {
+                unwrap_or_err!(this.handle(v.handle));
+            }
+            let dst = unwrap_or_err!(rewrite(this, ContainerWrapper::from(IoWrapper::new(io)), |handle| {
+                if let Some(v) = sections.iter().find(|v| v.handle == this.export_handle(handle)) {
+                    return (v.flags, v.threshold);
+                }
+                match options {
+                    Some(v) if v.override_all => (v.flags, v.threshold),
+                    _ => {
+                        let options = copy_options(&this.section_header(handle));
+                        (options.flags, options.threshold)
+                    }
+                }
+            }));
+            bytes_saved.set(unwrap_or_err!(bytes_saved_by(this, &dst)));
+            ERR_NONE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Write;
+    use crate::container::{CHECKSUM_CRC32, COMPRESSION_XZ, COMPRESSION_ZLIB};
+    use crate::container_wrapper::ContainerWrapper;
+    use crate::memory_io::MemoryIo;
+    use crate::test_utils;
+    use super::*;
+
+    #[test]
+    fn compress_all()
+    {
+        let mut src = test_utils::create();
+        let first = test_utils::content(8192, 1);
+        let second = test_utils::content(8192, 2);
+        test_utils::add_section(&mut src, 0, &first);
+        test_utils::add_section(&mut src, CHECKSUM_CRC32, &second);
+        let src = test_utils::reopen(src);
+        let dst = unsafe { rewrite(&src, ContainerWrapper::from(MemoryIo::owned()), |_| (COMPRESSION_ZLIB, 0)) }.unwrap();
+        let saved = bytes_saved_by(&src, &dst).unwrap();
+        assert!(saved > 0);
+        let size = src.get_main_header().file_size as i64;
+        let dst = test_utils::open(test_utils::into_bytes(dst));
+        assert_eq!(size - dst.get_main_header().file_size as i64, saved);
+        let handles: Vec<_> = dst.sections().iter().collect();
+        assert_eq!(handles.len(), 2);
+        for (handle, content) in handles.into_iter().zip([first, second]) {
+            assert_eq!(dst.sections().header(handle).flags & COMPRESSION_ZLIB, COMPRESSION_ZLIB);
+            assert_eq!(test_utils::read(&dst, handle), content);
+        }
+    }
+
+    #[test]
+    fn bytes_saved_uses_the_backend_size()
+    {
+        let mut src = test_utils::create();
+        test_utils::add_section(&mut src, 0, &test_utils::content(1024, 1));
+        src.save_changes().unwrap();
+        //Neither the unsaved section nor the trailing bytes are reflected by the main header
+        test_utils::add_section(&mut src, 0, &test_utils::content(1024, 2));
+        let size = src.get_main_header().file_size;
+        {
+            let mut backend = src.backend();
+            backend.seek(SeekFrom::End(0)).unwrap();
+            backend.write_all(&[0; 4096]).unwrap();
+        }
+        let dst = unsafe { rewrite(&src, ContainerWrapper::from(MemoryIo::owned()), |_| (COMPRESSION_XZ, 0)) }.unwrap();
+        let saved = bytes_saved_by(&src, &dst).unwrap();
+        assert_eq!(saved, size as i64 + 4096 - dst.get_main_header().file_size as i64);
+    }
+}
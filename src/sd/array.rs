@@ -26,8 +26,12 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::io::Write;
 use std::mem::MaybeUninit;
-use crate::sd::value::Value;
+use std::os::raw::c_uint;
+use crate::error_codes::ERR_NONE;
+use crate::sd::io::{enter, write_bytes, write_count};
+use crate::sd::value::{EncodeOptions, Value};
 use crate::ffi_helper::export;
 
 pub struct ArrayWrapper(Vec<Value>);
@@ -47,6 +51,10 @@ impl ArrayWrapper {
         drop(host);
     }
 
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+
     pub fn wrap(array: bpx::sd::Array) -> Self {
         //TODO: optimize once into_inner is implemented in bpx::sd::Array.
         let mut lst = Vec::with_capacity(array.len());
@@ -56,41 +64,55 @@ impl ArrayWrapper {
         Self(lst)
     }
 
-    pub unsafe fn to_array(&self) -> bpx::sd::Array {
+    pub unsafe fn to_array(&self, options: &EncodeOptions) -> bpx::sd::Array {
         let mut arr = bpx::sd::Array::with_capacity(self.0.len() as _);
         for v in &self.0 {
-            arr.as_mut().push(v.into_value())
+            arr.as_mut().push(v.into_value_with(options))
         }
         arr
     }
+
+    pub unsafe fn write<W: Write>(&self, out: &mut W, options: &EncodeOptions, max_depth: usize) -> Result<(), c_uint> {
+        let max_depth = enter(max_depth)?;
+        write_count(out, self.0.len())?;
+        for v in &self.0 {
+            write_bytes(out, &[v.type_code()])?;
+            v.write(out, options, max_depth)?;
+        }
+        Ok(())
+    }
 }
 
 export!
 {
-    fn bpx_sd_array_push(array: *mut ArrayWrapper, value: *mut Value)
+    fn bpx_sd_array_push(array: *mut ArrayWrapper, value: *mut Value) -> c_uint
     {
         (*array).0.push(*value);
         (*value).reset();
+        ERR_NONE
     }
 
-    fn bpx_sd_array_insert(array: *mut ArrayWrapper, value: *mut Value, index: usize)
+    fn bpx_sd_array_insert(array: *mut ArrayWrapper, value: *mut Value, index: usize) -> c_uint
     {
         (*array).0.insert(index, *value);
         (*value).reset();
+        ERR_NONE
     }
 
-    fn bpx_sd_array_remove(array: *mut ArrayWrapper, index: usize)
+    fn bpx_sd_array_remove(array: *mut ArrayWrapper, index: usize) -> c_uint
     {
         (*array).0.get_mut(index).map(|v| v.free());
         (*array).0.remove(index);
+        ERR_NONE
     }
 
-    fn bpx_sd_array_list(array: *const ArrayWrapper, out: *mut Value)
+    fn bpx_sd_array_list(array: *const ArrayWrapper, out: *mut Value) -> c_uint
     {
         let slice: &mut [MaybeUninit<Value>] = std::slice::from_raw_parts_mut(out as _, (*array).0.len());
         for (i, v) in (*array).0.iter().enumerate() {
             slice[i].write(*v);
         }
+        ERR_NONE
     }
 
     fn bpx_sd_array_get(array: *const ArrayWrapper, index: usize) -> Value
@@ -0,0 +1,219 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::os::raw::{c_char, c_uint};
+use crate::error_codes::ERR_NONE;
+use crate::ffi_helper::export;
+use crate::ffi_helper::OutCell;
+use super::io::MAX_DEPTH;
+use super::json::{into_c_string, key_name, symbol_table, write_string};
+use super::value::{Value, ValueRef};
+
+#[repr(C)]
+pub struct DumpOptions
+{
+    //Number of spaces per indentation level, 0 uses 2
+    pub indent: usize,
+    //Arrays and objects nested deeper than this are collapsed, 0 or anything above 256 uses 256
+    pub max_depth: usize,
+    //Maximum number of elements printed per array or object, 0 is unlimited
+    pub max_elements: usize,
+    //Optional table of key names, keys not found in it are printed as hex hashes
+    pub symbols: *const *const c_char,
+    pub symbol_count: usize
+}
+
+struct Dumper<'a>
+{
+    out: String,
+    indent: usize,
+    max_depth: usize,
+    max_elements: usize,
+    symbols: HashMap<u64, &'a str>
+}
+
+impl<'a> Dumper<'a>
+{
+    fn newline(&mut self, depth: usize)
+    {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat(' ').take(depth * self.indent));
+    }
+
+    fn truncated(&mut self, depth: usize, len: usize)
+    {
+        if self.max_elements != 0 && len > self.max_elements {
+            self.newline(depth + 1);
+            let _ = write!(self.out, "... {} more", len - self.max_elements);
+        }
+    }
+
+    unsafe fn value(&mut self, value: &Value, depth: usize)
+    {
+        let limit = match self.max_elements {
+            0 => usize::MAX,
+            n => n
+        };
+        let collapsed = depth >= self.max_depth;
+        let _ = match value.view() {
+            ValueRef::Null => write!(self.out, "null"),
+            ValueRef::Bool(v) => write!(self.out, "bool {}", v),
+            ValueRef::Uint8(v) => write!(self.out, "u8 {}", v),
+            ValueRef::Uint16(v) => write!(self.out, "u16 {}", v),
+            ValueRef::Uint32(v) => write!(self.out, "u32 {}", v),
+            ValueRef::Uint64(v) => write!(self.out, "u64 {}", v),
+            ValueRef::Int8(v) => write!(self.out, "i8 {}", v),
+            ValueRef::Int16(v) => write!(self.out, "i16 {}", v),
+            ValueRef::Int32(v) => write!(self.out, "i32 {}", v),
+            ValueRef::Int64(v) => write!(self.out, "i64 {}", v),
+            ValueRef::Float(v) => write!(self.out, "f32 {:?}", v),
+            ValueRef::Double(v) => write!(self.out, "f64 {:?}", v),
+            ValueRef::String(v) => {
+                self.out.push_str("string ");
+                write_string(&mut self.out, v);
+                Ok(())
+            },
+            ValueRef::Array(v) => {
+                let values = v.values();
+                if collapsed || values.is_empty() {
+                    let _ = write!(self.out, "array({}) [{}]", values.len(), if values.is_empty() { "" } else { "..." });
+                    return;
+                }
+                let _ = write!(self.out, "array({}) [", values.len());
+                for v in values.iter().take(limit) {
+                    self.newline(depth + 1);
+                    self.value(v, depth + 1);
+                }
+                self.truncated(depth, values.len());
+                self.newline(depth);
+                write!(self.out, "]")
+            },
+            ValueRef::Object(v) => {
+                if collapsed || v.is_empty() {
+                    let _ = write!(self.out, "object({}) {{{}}}", v.len(), if v.is_empty() { "" } else { "..." });
+                    return;
+                }
+                let _ = write!(self.out, "object({}) {{", v.len());
+                for (k, v, name) in v.entries().take(limit) {
+                    self.newline(depth + 1);
+                    let name = key_name(&self.symbols, name, k);
+                    let _ = write!(self.out, "{}: ", name);
+                    self.value(v, depth + 1);
+                }
+                self.truncated(depth, v.len());
+                self.newline(depth);
+                write!(self.out, "}}")
+            }
+        };
+    }
+}
+
+export!
+{
+    fn bpx_sd_value_dump(value: *const Value, options: *const DumpOptions, out: OutCell<*mut c_char>) -> c_uint
+    {
+        let mut dumper = Dumper {
+            out: String::new(),
+            indent: 2,
+            max_depth: MAX_DEPTH,
+            max_elements: 0,
+            symbols: HashMap::new()
+        };
+        if let Some(options) = options.as_ref() {
+            if options.indent != 0 {
+                dumper.indent = options.indent;
+            }
+            if options.max_depth != 0 {
+                dumper.max_depth = options.max_depth.min(MAX_DEPTH);
+            }
+            dumper.max_elements = options.max_elements;
+            dumper.symbols = symbol_table(options.symbols, options.symbol_count);
+        }
+        dumper.value(&*value, 0);
+        out.set(into_c_string(dumper.out));
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::ffi::CString;
+    use crate::sd::array::{bpx_sd_array_push, ArrayWrapper};
+    use crate::sd::object::{bpx_sd_object_set, ObjectWrapper};
+    use super::*;
+
+    fn dump(value: &Value, max_depth: usize, max_elements: usize) -> String
+    {
+        let mut dumper = Dumper {
+            out: String::new(),
+            indent: 1,
+            max_depth,
+            max_elements,
+            symbols: HashMap::new()
+        };
+        unsafe { dumper.value(value, 0) };
+        dumper.out
+    }
+
+    #[test]
+    fn insertion_order()
+    {
+        unsafe {
+            let mut obj = ObjectWrapper::new();
+            for (k, v) in [("b", 1u8), ("a", 2), ("c", 3)] {
+                let key = CString::new(k).unwrap();
+                bpx_sd_object_set(&mut obj, key.as_ptr(), &mut Value::wrap(v.into()));
+            }
+            let mut value = Value::object(obj);
+            assert_eq!(dump(&value, MAX_DEPTH, 0), "object(3) {\n b: u8 1\n a: u8 2\n c: u8 3\n}");
+            assert_eq!(dump(&value, MAX_DEPTH, 2), "object(3) {\n b: u8 1\n a: u8 2\n ... 1 more\n}");
+            assert_eq!(dump(&value, 0, 0), "object(3) {...}");
+            value.free();
+        }
+    }
+
+    #[test]
+    fn depth_limit()
+    {
+        unsafe {
+            let mut value = Value::null();
+            for _ in 0..MAX_DEPTH * 4 {
+                let mut array = ArrayWrapper::new();
+                bpx_sd_array_push(&mut array, &mut value);
+                value = Value::array(array);
+            }
+            let out = dump(&value, MAX_DEPTH, 0);
+            assert_eq!(out.matches("array(1) [\n").count(), MAX_DEPTH);
+            assert!(out.contains("array(1) [...]"));
+            value.free();
+        }
+    }
+}
@@ -26,12 +26,67 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::error_codes::{CErrCode, ERR_NONE};
+use crate::error_codes::{CErrCode, ERR_NONE, ERR_SD_MAX_DEPTH_EXCEEDED};
 use crate::error_codes::unwrap_or_err;
+use std::io::Write;
 use std::os::raw::c_uint;
 use crate::ffi_helper::export;
-use crate::types::Section;
-use super::value::Value;
+use crate::ffi_helper::OutCell;
+use crate::types::{Buffer, Section};
+use crate::last_error::LastError;
+use super::value::{EncodeOptions, Value, ValueType};
+
+//Sink which only counts the number of bytes written to it
+struct SizeCounter(usize);
+
+impl Write for SizeCounter
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+//Maximum depth of nested arrays and objects, the root object included
+pub const MAX_DEPTH: usize = 256;
+
+//Errors of the destination are reported like the ones the BPX encoder runs into
+pub fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> Result<(), c_uint>
+{
+    out.write_all(bytes).map_err(|e| bpx::sd::error::Error::Io(e).cerr_code())
+}
+
+pub fn write_count<W: Write>(out: &mut W, count: usize) -> Result<(), c_uint>
+{
+    if count > 255 {
+        return Err(bpx::sd::error::Error::CapacityExceeded(count).cerr_code());
+    }
+    write_bytes(out, &[count as u8])
+}
+
+//Called before writing an array or an object, returns the maximum depth left for its content
+pub fn enter(max_depth: usize) -> Result<usize, c_uint>
+{
+    if max_depth <= 1 {
+        return Err(LastError::new(ERR_SD_MAX_DEPTH_EXCEEDED, format!("maximum depth for nested values exceeded ({})", MAX_DEPTH)).set());
+    }
+    Ok(max_depth - 1)
+}
+
+unsafe fn encode<W: Write>(value: *const Value, options: *const EncodeOptions, dest: &mut W) -> Result<(), c_uint>
+{
+    let options = options.as_ref().copied().unwrap_or_default();
+    if !matches!((*value).ty, ValueType::Object) {
+        return Err(bpx::sd::error::Error::NotAnObject.cerr_code());
+    }
+    (*value).write(dest, &options, MAX_DEPTH)
+}
 
 export!
 {
@@ -52,7 +107,75 @@ export!
 
     fn bpx_sd_value_encode(section: *mut Section, value: *const Value) -> c_uint
     {
-        let value = (*value).into_value();
-        value.write(&mut **section).map(|_| ERR_NONE).unwrap_or_else(|v| v.cerr_code())
+        bpx_sd_value_encode2(section, value, std::ptr::null())
+    }
+
+    fn bpx_sd_value_encode_memory(value: *const Value, out: OutCell<Buffer>) -> c_uint
+    {
+        bpx_sd_value_encode_memory2(value, std::ptr::null(), out)
+    }
+
+    fn bpx_sd_value_encoded_size(value: *const Value, size: OutCell<usize>) -> c_uint
+    {
+        bpx_sd_value_encoded_size2(value, std::ptr::null(), size)
+    }
+
+    fn bpx_sd_value_encode2(section: *mut Section, value: *const Value, options: *const EncodeOptions) -> c_uint
+    {
+        unwrap_or_err!((*section).check_writable());
+        //Encoded in memory first so that a failed encoding leaves the section untouched
+        let mut data = Vec::new();
+        unwrap_or_err!(encode(value, options, &mut data));
+        unwrap_or_err!(write_bytes(&mut **section, &data));
+        ERR_NONE
+    }
+
+    fn bpx_sd_value_encode_memory2(value: *const Value, options: *const EncodeOptions, out: OutCell<Buffer>) -> c_uint
+    {
+        let mut data = Vec::new();
+        unwrap_or_err!(encode(value, options, &mut data));
+        out.set(Buffer::new(data));
+        ERR_NONE
+    }
+
+    fn bpx_sd_value_encoded_size2(value: *const Value, options: *const EncodeOptions, size: OutCell<usize>) -> c_uint
+    {
+        let mut counter = SizeCounter(0);
+        unwrap_or_err!(encode(value, options, &mut counter));
+        size.set(counter.0);
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn size_matches_encoding()
+    {
+        let mut array = bpx::sd::Array::new();
+        array.as_mut().push("text".into());
+        array.as_mut().push(1.5f64.into());
+        let mut obj = bpx::sd::Object::new();
+        obj.set("a", 1u8.into());
+        obj.set("b", array.into());
+        let mut value = Value::wrap(obj.into());
+        let options = EncodeOptions {
+            debug_symbols: true,
+            canonical: true
+        };
+        let mut data = Vec::new();
+        let mut counter = SizeCounter(0);
+        unsafe {
+            encode(&value, &options, &mut data).unwrap();
+            encode(&value, &options, &mut counter).unwrap();
+            value.free();
+        }
+        assert_eq!(counter.0, data.len());
+        let mut value = Value::wrap(1u8.into());
+        assert!(unsafe { encode(&value, std::ptr::null(), &mut counter) }.is_err());
+        unsafe { value.free() };
     }
 }
@@ -0,0 +1,813 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::os::raw::{c_char, c_uint};
+use bpx::utils::Name;
+use crate::error_codes::{ERR_NONE, ERR_SD_JSON_SYNTAX, ERR_SD_JSON_TYPE, ERR_SD_UTF8};
+use crate::error_codes::unwrap_or_err;
+use crate::ffi_helper::export;
+use crate::ffi_helper::OutCell;
+use crate::last_error::LastError;
+use super::io::{enter, MAX_DEPTH};
+use super::object::DEBUG_KEY;
+use super::value::{Value, ValueRef};
+
+#[repr(C)]
+pub struct JsonOptions
+{
+    //Numbers are written as {"$u16": 5} so that bpx_sd_value_from_json restores their exact type
+    pub tagged: bool,
+    //Optional table of key names, keys not found in it are written as hex hashes
+    pub symbols: *const *const c_char,
+    pub symbol_count: usize
+}
+
+pub unsafe fn symbol_table<'a>(symbols: *const *const c_char, count: usize) -> HashMap<u64, &'a str>
+{
+    if symbols.is_null() {
+        return HashMap::new();
+    }
+    std::slice::from_raw_parts(symbols, count).iter()
+        .filter_map(|v| CStr::from_ptr(*v).to_str().ok())
+        .map(|v| (bpx::utils::hash(v), v))
+        .collect()
+}
+
+//Keys which are not hex hashes (see key_name) are hashed names
+fn key_hash(key: &str) -> Option<u64>
+{
+    match key.strip_prefix("0x") {
+        //from_str_radix alone would also accept a sign
+        Some(hex) if hex.len() == 16 && hex.bytes().all(|c| c.is_ascii_hexdigit()) => u64::from_str_radix(hex, 16).ok(),
+        _ => None
+    }
+}
+
+//Names from the debug symbols of the object take precedence over the user supplied symbol table
+pub fn key_name(symbols: &HashMap<u64, &str>, name: Option<&CStr>, hash: u64) -> String
+{
+    match name {
+        Some(v) => v.to_string_lossy().into_owned(),
+        None => match symbols.get(&hash) {
+            Some(v) => v.to_string(),
+            None => format!("0x{:016x}", hash)
+        }
+    }
+}
+
+//Strings are checked for null characters when they enter a value, so printed values cannot contain any
+pub fn into_c_string(s: String) -> *mut c_char
+{
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+pub fn write_string(out: &mut String, s: &str)
+{
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+//Untagged floats always carry a fraction or exponent so that they read back as floats; non finite values have no
+// JSON representation and become null unless tagged (as strings).
+fn write_float(out: &mut String, v: impl std::fmt::Display, finite: bool, tag: Option<&str>)
+{
+    match (tag, finite) {
+        (Some(tag), true) => { let _ = write!(out, "{{\"{}\": {}}}", tag, v); },
+        (Some(tag), false) => { let _ = write!(out, "{{\"{}\": \"{}\"}}", tag, v); },
+        (None, true) => {
+            let s = v.to_string();
+            out.push_str(&s);
+            if !s.contains(['.', 'e', 'E']) {
+                out.push_str(".0");
+            }
+        },
+        (None, false) => out.push_str("null")
+    }
+}
+
+struct JsonWriter<'a>
+{
+    out: String,
+    tagged: bool,
+    symbols: HashMap<u64, &'a str>
+}
+
+impl<'a> JsonWriter<'a>
+{
+    fn int(&mut self, tag: &str, v: impl std::fmt::Display)
+    {
+        if self.tagged {
+            let _ = write!(self.out, "{{\"{}\": {}}}", tag, v);
+        } else {
+            let _ = write!(self.out, "{}", v);
+        }
+    }
+
+    //Same depth limit as the parser and the encoder
+    unsafe fn value(&mut self, value: &Value, max_depth: usize) -> Result<(), c_uint>
+    {
+        let tagged = self.tagged;
+        match value.view() {
+            ValueRef::Null => self.out.push_str("null"),
+            ValueRef::Bool(v) => self.out.push_str(if v { "true" } else { "false" }),
+            ValueRef::Uint8(v) => self.int("$u8", v),
+            ValueRef::Uint16(v) => self.int("$u16", v),
+            ValueRef::Uint32(v) => self.int("$u32", v),
+            ValueRef::Uint64(v) => self.int("$u64", v),
+            ValueRef::Int8(v) => self.int("$i8", v),
+            ValueRef::Int16(v) => self.int("$i16", v),
+            ValueRef::Int32(v) => self.int("$i32", v),
+            ValueRef::Int64(v) => self.int("$i64", v),
+            ValueRef::Float(v) => write_float(&mut self.out, v, v.is_finite(), tagged.then_some("$f32")),
+            ValueRef::Double(v) => write_float(&mut self.out, v, v.is_finite(), tagged.then_some("$f64")),
+            ValueRef::String(v) => write_string(&mut self.out, v),
+            ValueRef::Array(v) => {
+                let max_depth = enter(max_depth)?;
+                self.out.push('[');
+                for (i, v) in v.values().iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.value(v, max_depth)?;
+                }
+                self.out.push(']');
+            },
+            ValueRef::Object(v) => {
+                let max_depth = enter(max_depth)?;
+                self.out.push('{');
+                for (i, (k, v, name)) in v.entries().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    let name = key_name(&self.symbols, name, k);
+                    write_string(&mut self.out, &name);
+                    self.out.push_str(": ");
+                    self.value(v, max_depth)?;
+                }
+                self.out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+struct JsonParser<'a>
+{
+    data: &'a str,
+    pos: usize,
+    depth: usize
+}
+
+impl<'a> JsonParser<'a>
+{
+    fn error(&self, msg: &str) -> c_uint
+    {
+        LastError::new(ERR_SD_JSON_SYNTAX, format!("json syntax error at offset {}: {}", self.pos, msg)).set()
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        self.data.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), c_uint>
+    {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, s: &str, value: bpx::sd::Value) -> Result<bpx::sd::Value, c_uint>
+    {
+        if !self.data[self.pos..].starts_with(s) {
+            return Err(self.error("unknown literal"));
+        }
+        self.pos += s.len();
+        Ok(value)
+    }
+
+    fn hex4(&mut self) -> Result<u32, c_uint>
+    {
+        let hex = self.data.get(self.pos..self.pos + 4).ok_or_else(|| self.error("truncated unicode escape"))?;
+        if !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let v = u32::from_str_radix(hex, 16).map_err(|_| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(v)
+    }
+
+    fn string(&mut self) -> Result<String, c_uint>
+    {
+        self.expect(b'"')?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(c) = self.peek() {
+                if c == b'"' || c == b'\\' || c < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            s.push_str(&self.data[start..self.pos]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = self.peek().ok_or_else(|| self.error("truncated escape"))?;
+                    self.pos += 1;
+                    match c {
+                        b'"' => s.push('"'),
+                        b'\\' => s.push('\\'),
+                        b'/' => s.push('/'),
+                        b'b' => s.push('\u{8}'),
+                        b'f' => s.push('\u{c}'),
+                        b'n' => s.push('\n'),
+                        b'r' => s.push('\r'),
+                        b't' => s.push('\t'),
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) && self.data[self.pos..].starts_with("\\u") {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            s.push(char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))?);
+                        },
+                        _ => return Err(self.error("invalid escape"))
+                    }
+                },
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string"))
+            }
+        }
+    }
+
+    fn number_token(&mut self) -> Result<&'a str, c_uint>
+    {
+        self.skip_whitespace();
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let start = p.pos;
+            while let Some(b'0'..=b'9') = p.peek() {
+                p.pos += 1;
+            }
+            p.pos > start
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        if !digits(self) {
+            return Err(self.error("expected a number"));
+        }
+        if self.pos - int_start > 1 && self.data.as_bytes()[int_start] == b'0' {
+            return Err(self.error("leading zeros are not allowed"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("expected digits after '.'"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+        Ok(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<bpx::sd::Value, c_uint>
+    {
+        let token = self.number_token()?;
+        if !token.contains(['.', 'e', 'E']) {
+            if let Ok(v) = token.parse::<i64>() {
+                return Ok(v.into());
+            }
+            if let Ok(v) = token.parse::<u64>() {
+                return Ok(v.into());
+            }
+        }
+        token.parse::<f64>().map(|v| v.into()).map_err(|_| self.error("invalid number"))
+    }
+
+    fn tagged(&mut self, tag: &str) -> Result<bpx::sd::Value, c_uint>
+    {
+        fn parse<T: std::str::FromStr + Into<bpx::sd::Value>>(tag: &str, token: &str) -> Result<bpx::sd::Value, c_uint>
+        {
+            token.parse::<T>().map(|v| v.into()).map_err(|_| {
+                LastError::new(ERR_SD_JSON_TYPE, format!("value {} is not a valid {}", token, tag)).set()
+            })
+        }
+        self.skip_whitespace();
+        //Non finite floats are written as strings
+        let token = match self.peek() {
+            Some(b'"') if tag == "$f32" || tag == "$f64" => {
+                let token = self.string()?;
+                if !matches!(&*token, "inf" | "-inf" | "NaN") {
+                    return Err(LastError::new(ERR_SD_JSON_TYPE, format!("value \"{}\" is not a non finite {}", token, tag)).set());
+                }
+                token
+            },
+            _ => self.number_token()?.into()
+        };
+        let value = match tag {
+            "$u8" => parse::<u8>(tag, &token),
+            "$u16" => parse::<u16>(tag, &token),
+            "$u32" => parse::<u32>(tag, &token),
+            "$u64" => parse::<u64>(tag, &token),
+            "$i8" => parse::<i8>(tag, &token),
+            "$i16" => parse::<i16>(tag, &token),
+            "$i32" => parse::<i32>(tag, &token),
+            "$i64" => parse::<i64>(tag, &token),
+            "$f32" => parse::<f32>(tag, &token),
+            _ => parse::<f64>(tag, &token)
+        }?;
+        self.expect(b'}')?;
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<bpx::sd::Value, c_uint>
+    {
+        self.expect(b'{')?;
+        let mut obj = bpx::sd::Object::new();
+        //Names of the keys are kept as debug symbols, once per key
+        let mut symbols = bpx::sd::Array::new();
+        let mut named = HashSet::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(obj.into());
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            if obj.len() == 0 {
+                if let "$u8" | "$u16" | "$u32" | "$u64" | "$i8" | "$i16" | "$i32" | "$i64" | "$f32" | "$f64" = &*key {
+                    return self.tagged(&key);
+                }
+            }
+            let value = self.value()?;
+            let hash = key_hash(&key).unwrap_or_else(|| {
+                let hash = bpx::utils::hash(&key);
+                if named.insert(hash) {
+                    symbols.as_mut().push(key.into());
+                }
+                hash
+            });
+            obj.set(Name::from(hash), value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    if symbols.len() > 0 {
+                        obj.set(Name::from(bpx::utils::hash(DEBUG_KEY)), symbols.into());
+                    }
+                    return Ok(obj.into());
+                },
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<bpx::sd::Value, c_uint>
+    {
+        self.expect(b'[')?;
+        let mut arr = bpx::sd::Array::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(arr.into());
+        }
+        loop {
+            let value = self.value()?;
+            arr.as_mut().push(value);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(arr.into());
+                },
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<bpx::sd::Value, c_uint>
+    {
+        self.skip_whitespace();
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("maximum nesting depth exceeded"));
+        }
+        self.depth += 1;
+        let value = match self.peek() {
+            Some(b'n') => self.literal("null", bpx::sd::Value::Null),
+            Some(b't') => self.literal("true", true.into()),
+            Some(b'f') => self.literal("false", false.into()),
+            Some(b'"') => {
+                let s = self.string()?;
+                if s.contains('\0') {
+                    return Err(LastError::new(ERR_SD_JSON_TYPE, "strings cannot contain null characters").set());
+                }
+                Ok(s.into())
+            },
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input"))
+        };
+        self.depth -= 1;
+        value
+    }
+}
+
+//Parses a whole JSON document, nothing but whitespace may follow the value
+fn parse(data: &str) -> Result<bpx::sd::Value, c_uint>
+{
+    let mut parser = JsonParser {
+        data,
+        pos: 0,
+        depth: 0
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != data.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+export!
+{
+    //options may be NULL, the returned string must be freed with bpx_string_free
+    fn bpx_sd_value_to_json(value: *const Value, options: *const JsonOptions, out: OutCell<*mut c_char>) -> c_uint
+    {
+        let mut writer = match options.as_ref() {
+            Some(options) => JsonWriter {
+                out: String::new(),
+                tagged: options.tagged,
+                symbols: symbol_table(options.symbols, options.symbol_count)
+            },
+            None => JsonWriter {
+                out: String::new(),
+                tagged: false,
+                symbols: HashMap::new()
+            }
+        };
+        unwrap_or_err!(writer.value(&*value, MAX_DEPTH));
+        out.set(into_c_string(writer.out));
+        ERR_NONE
+    }
+
+    fn bpx_sd_value_from_json(json: *const c_char, out: OutCell<Value>) -> c_uint
+    {
+        let data = unwrap_or_err!(CStr::from_ptr(json).to_str().map_err(|_| LastError::new(ERR_SD_UTF8, "utf8 error").set()));
+        let value = unwrap_or_err!(parse(data));
+        out.set(Value::wrap(value));
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::error_codes::ERR_SD_MAX_DEPTH_EXCEEDED;
+    use crate::sd::array::{bpx_sd_array_push, ArrayWrapper};
+    use crate::sd::object::{bpx_sd_object_set, ObjectWrapper};
+    use super::*;
+
+    fn write(value: &Value, tagged: bool, symbols: HashMap<u64, &str>) -> Result<String, c_uint>
+    {
+        let mut writer = JsonWriter {
+            out: String::new(),
+            tagged,
+            symbols
+        };
+        unsafe { writer.value(value, MAX_DEPTH) }.map(|_| writer.out)
+    }
+
+    fn to_json(value: &bpx::sd::Value, tagged: bool) -> String
+    {
+        let mut value = Value::wrap(value.clone());
+        let json = write(&value, tagged, HashMap::new()).unwrap();
+        unsafe { value.free() };
+        json
+    }
+
+    fn type_name(value: &bpx::sd::Value) -> &'static str
+    {
+        match value {
+            bpx::sd::Value::Null => "null",
+            bpx::sd::Value::Bool(_) => "bool",
+            bpx::sd::Value::Uint8(_) => "u8",
+            bpx::sd::Value::Uint16(_) => "u16",
+            bpx::sd::Value::Uint32(_) => "u32",
+            bpx::sd::Value::Uint64(_) => "u64",
+            bpx::sd::Value::Int8(_) => "i8",
+            bpx::sd::Value::Int16(_) => "i16",
+            bpx::sd::Value::Int32(_) => "i32",
+            bpx::sd::Value::Int64(_) => "i64",
+            bpx::sd::Value::Float(_) => "f32",
+            bpx::sd::Value::Double(_) => "f64",
+            bpx::sd::Value::String(_) => "string",
+            bpx::sd::Value::Array(_) => "array",
+            bpx::sd::Value::Object(_) => "object"
+        }
+    }
+
+    fn all_types() -> bpx::sd::Value
+    {
+        let values: [bpx::sd::Value; 18] = [
+            bpx::sd::Value::Null,
+            true.into(),
+            200u8.into(),
+            60000u16.into(),
+            u32::MAX.into(),
+            u64::MAX.into(),
+            (-100i8).into(),
+            i16::MIN.into(),
+            i32::MIN.into(),
+            i64::MIN.into(),
+            1.5f32.into(),
+            0.1f64.into(),
+            f32::INFINITY.into(),
+            f32::NEG_INFINITY.into(),
+            f32::NAN.into(),
+            f64::INFINITY.into(),
+            f64::NAN.into(),
+            String::from("quote \" backslash \\ newline \n control \u{1} emoji \u{1F600}").into()
+        ];
+        let mut array = bpx::sd::Array::new();
+        for v in values {
+            array.as_mut().push(v);
+        }
+        let mut inner = bpx::sd::Object::new();
+        inner.set(Name::from(1u64), 1u8.into());
+        let mut obj = bpx::sd::Object::new();
+        obj.set(Name::from(2u64), array.into());
+        obj.set(Name::from(3u64), inner.into());
+        obj.into()
+    }
+
+    #[test]
+    fn tagged_round_trip()
+    {
+        let json = to_json(&all_types(), true);
+        let value = parse(&json).unwrap();
+        assert_eq!(to_json(&value, true), json);
+        let obj = match &value {
+            bpx::sd::Value::Object(obj) => obj,
+            _ => panic!("expected an object")
+        };
+        let mut entries: Vec<(u64, &bpx::sd::Value)> = obj.into_iter().map(|(k, v)| (k.into_inner(), v)).collect();
+        entries.sort_by_key(|(k, _)| *k);
+        assert!(obj.get(DEBUG_KEY).is_none());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 2);
+        assert_eq!(entries[1].0, 3);
+        let values: Vec<&bpx::sd::Value> = match entries[0].1 {
+            bpx::sd::Value::Array(array) => array.into_iter().collect(),
+            _ => panic!("expected an array")
+        };
+        let types: Vec<&str> = values.iter().map(|v| type_name(v)).collect();
+        assert_eq!(types, [
+            "null", "bool", "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64",
+            "f32", "f64", "f32", "f32", "f32", "f64", "f64", "string"
+        ]);
+        assert!(matches!(values[5], bpx::sd::Value::Uint64(v) if *v == u64::MAX));
+        assert!(matches!(values[9], bpx::sd::Value::Int64(v) if *v == i64::MIN));
+        assert!(matches!(values[11], bpx::sd::Value::Double(v) if *v == 0.1));
+        assert!(matches!(values[12], bpx::sd::Value::Float(v) if *v == f32::INFINITY));
+        assert!(matches!(values[13], bpx::sd::Value::Float(v) if *v == f32::NEG_INFINITY));
+        assert!(matches!(values[14], bpx::sd::Value::Float(v) if v.is_nan()));
+        assert!(matches!(values[15], bpx::sd::Value::Double(v) if *v == f64::INFINITY));
+        assert!(matches!(values[16], bpx::sd::Value::Double(v) if v.is_nan()));
+        assert!(matches!(entries[1].1, bpx::sd::Value::Object(_)));
+    }
+
+    #[test]
+    fn insertion_order()
+    {
+        unsafe {
+            let mut obj = ObjectWrapper::new();
+            for (k, v) in [("b", 1u8), ("a", 2)] {
+                let key = CString::new(k).unwrap();
+                bpx_sd_object_set(&mut obj, key.as_ptr(), &mut Value::wrap(v.into()));
+            }
+            obj.insert_or_replace(5, Value::wrap(3u8.into()));
+            obj.insert_or_replace(6, Value::wrap(4u8.into()));
+            let mut value = Value::object(obj);
+            let json = write(&value, false, HashMap::from([(5, "c")])).unwrap();
+            assert_eq!(json, "{\"b\": 1, \"a\": 2, \"c\": 3, \"0x0000000000000006\": 4}");
+            value.free();
+        }
+    }
+
+    #[test]
+    fn names_of_large_objects()
+    {
+        unsafe {
+            let mut obj = ObjectWrapper::new();
+            for i in 0..300 {
+                let key = CString::new(format!("key{}", i)).unwrap();
+                bpx_sd_object_set(&mut obj, key.as_ptr(), &mut Value::wrap(bpx::sd::Value::Null));
+            }
+            let mut value = Value::object(obj);
+            let json = write(&value, false, HashMap::new()).unwrap();
+            assert!(json.starts_with("{\"key0\": null, "));
+            assert!(json.ends_with(", \"key299\": null}"));
+            value.free();
+        }
+    }
+
+    #[test]
+    fn depth_limit()
+    {
+        unsafe {
+            let mut value = Value::null();
+            for depth in 1..=MAX_DEPTH {
+                let mut array = ArrayWrapper::new();
+                bpx_sd_array_push(&mut array, &mut value);
+                value = Value::array(array);
+                let json = write(&value, false, HashMap::new());
+                match depth < MAX_DEPTH {
+                    true => assert_eq!(json.unwrap().len(), depth * 2 + 4),
+                    false => assert_eq!(json.err(), Some(ERR_SD_MAX_DEPTH_EXCEEDED))
+                }
+            }
+            value.free();
+        }
+    }
+
+    #[test]
+    fn untagged_floats()
+    {
+        let mut array = bpx::sd::Array::new();
+        array.as_mut().push(2.0f64.into());
+        array.as_mut().push(f64::NAN.into());
+        let json = to_json(&array.into(), false);
+        assert_eq!(json, "[2.0, null]");
+        let value = parse(&json).unwrap();
+        let types: Vec<&str> = match &value {
+            bpx::sd::Value::Array(array) => array.into_iter().map(type_name).collect(),
+            _ => panic!("expected an array")
+        };
+        assert_eq!(types, ["f64", "null"]);
+    }
+
+    #[test]
+    fn numbers()
+    {
+        assert!(matches!(parse("0"), Ok(bpx::sd::Value::Int64(0))));
+        assert!(matches!(parse("-0"), Ok(bpx::sd::Value::Int64(0))));
+        assert!(matches!(parse("10"), Ok(bpx::sd::Value::Int64(10))));
+        assert!(matches!(parse("0.5"), Ok(bpx::sd::Value::Double(_))));
+        assert!(matches!(parse("0e1"), Ok(bpx::sd::Value::Double(_))));
+    }
+
+    #[test]
+    fn duplicate_keys()
+    {
+        let value = parse("{\"a\": 1, \"b\": 2, \"a\": 3}").unwrap();
+        let obj = match &value {
+            bpx::sd::Value::Object(obj) => obj,
+            _ => panic!("expected an object")
+        };
+        let symbols: Vec<&str> = match obj.get(DEBUG_KEY) {
+            Some(bpx::sd::Value::Array(symbols)) => symbols.into_iter().map(|v| match v {
+                bpx::sd::Value::String(s) => &**s,
+                _ => panic!("expected a string")
+            }).collect(),
+            _ => panic!("expected debug symbols")
+        };
+        assert_eq!(symbols, ["a", "b"]);
+        assert!(matches!(obj.get("a"), Some(bpx::sd::Value::Int64(3))));
+    }
+
+    #[test]
+    fn key_hashes()
+    {
+        assert_eq!(key_hash("0x00000000000000ff"), Some(255));
+        assert_eq!(key_hash("0x+0000000000000ff"), None);
+        assert_eq!(key_hash("0x-0000000000000ff"), None);
+        assert_eq!(key_hash("0xff"), None);
+        assert_eq!(key_hash("ff00000000000000ff"), None);
+    }
+
+    #[test]
+    fn malformed()
+    {
+        let syntax = [
+            "",
+            "   ",
+            "{",
+            "[1, 2",
+            "[1,]",
+            "{\"a\" 1}",
+            "{\"a\": 1,}",
+            "{1: 2}",
+            "tru",
+            "nul",
+            "[1] x",
+            "\"abc",
+            "\"\\x\"",
+            "\"\\u12\"",
+            "\"\\u+123\"",
+            "\"\\u-123\"",
+            "\"\\ud800\"",
+            "\"a\u{1}b\"",
+            "+1",
+            "01",
+            "-01",
+            "00.5",
+            "1.",
+            ".5",
+            "1e",
+            "-"
+        ];
+        for json in syntax {
+            assert_eq!(parse(json).err(), Some(ERR_SD_JSON_SYNTAX), "{:?}", json);
+        }
+        let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert_eq!(parse(&deep).err(), Some(ERR_SD_JSON_SYNTAX));
+        let types = [
+            "{\"$u8\": 256}",
+            "{\"$u8\": -1}",
+            "{\"$i8\": 1.5}",
+            "{\"$u64\": 18446744073709551616}",
+            "{\"$f64\": \"+inf\"}",
+            "{\"$f32\": \"1.5\"}",
+            "{\"$u8\": \"1\"}",
+            "\"\\u0000\""
+        ];
+        for json in types {
+            assert!(parse(json).is_err(), "{:?}", json);
+        }
+        assert_eq!(parse("{\"$u8\": 256}").err(), Some(ERR_SD_JSON_TYPE));
+        assert_eq!(parse("{\"$f64\": \"+inf\"}").err(), Some(ERR_SD_JSON_TYPE));
+        assert_eq!(parse("\"\\u0000\"").err(), Some(ERR_SD_JSON_TYPE));
+    }
+}
@@ -30,3 +30,5 @@ mod value;
 mod object;
 mod array;
 mod io;
+mod json;
+mod dump;
@@ -27,17 +27,49 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io::Write;
 use std::mem::MaybeUninit;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_uint};
+use crate::error_codes::ERR_NONE;
 use bpx::utils::Name;
-use crate::sd::value::Value;
+use crate::sd::io::{enter, write_bytes, write_count};
+use crate::sd::value::{EncodeOptions, Value, ValueType};
 use crate::ffi_helper::export;
 
-pub struct ObjectWrapper(HashMap<u64, Value>);
+//Key of the array of key names stored in objects encoded with debug symbols
+pub const DEBUG_KEY: &str = "__debug__";
+
+pub struct ObjectWrapper
+{
+    //Insertion ordered so that listing and encoding do not depend on hashing, index maps a key to its entry
+    entries: Vec<(u64, Value)>,
+    index: HashMap<u64, usize>,
+    //Known key names, from bpx_sd_object_set or from the debug symbols of a decoded object
+    names: HashMap<u64, CString>
+}
+
+//Reads the debug symbols of an object, invalid symbol tables are ignored
+pub fn debug_symbols(object: &bpx::sd::Object) -> HashMap<u64, String>
+{
+    let mut names = HashMap::new();
+    if let Some(bpx::sd::Value::Array(symbols)) = object.get(DEBUG_KEY) {
+        for v in symbols {
+            if let bpx::sd::Value::String(name) = v {
+                names.insert(bpx::utils::hash(name), name.clone());
+            }
+        }
+    }
+    names
+}
 
 impl ObjectWrapper {
     pub fn new() -> ObjectWrapper {
-        ObjectWrapper(HashMap::new())
+        ObjectWrapper {
+            entries: Vec::new(),
+            index: HashMap::new(),
+            names: HashMap::new()
+        }
     }
 
     pub fn into_raw(self) -> *mut ObjectWrapper {
@@ -50,34 +82,136 @@ impl ObjectWrapper {
         drop(host);
     }
 
+    pub fn get(&self, hash: u64) -> Option<&Value> {
+        self.index.get(&hash).map(|i| &self.entries[*i].1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //Entries in insertion order with their key name when known
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &Value, Option<&CStr>)> {
+        self.entries.iter().map(|(k, v)| (*k, v, self.names.get(k).map(|v| v.as_c_str())))
+    }
+
+    //Replacing a value keeps the position of its key
     pub unsafe fn insert_or_replace(&mut self, hash: u64, value: Value) {
-        if let Some(mut old) = self.0.insert(hash, value) {
-            old.free();
+        match self.index.get(&hash) {
+            Some(i) => std::mem::replace(&mut self.entries[*i].1, value).free(),
+            None => {
+                self.index.insert(hash, self.entries.len());
+                self.entries.push((hash, value));
+            }
         }
     }
 
     pub fn wrap(object: bpx::sd::Object) -> Self {
         //TODO: optimize once into_inner is implemented in bpx::sd::Object.
-        let mut map = HashMap::with_capacity(object.len());
-        for (k, v) in &object {
-            map.insert(k.into_inner(), Value::wrap(v.clone()));
+        let debug_key = bpx::utils::hash(DEBUG_KEY);
+        let mut wrapper = Self::new();
+        let mut keys: Vec<(u64, &bpx::sd::Value)> = object.into_iter()
+            .map(|(k, v)| (k.into_inner(), v))
+            .filter(|(k, _)| *k != debug_key)
+            .collect();
+        //The original key order is not preserved by decoding, sort by hash to keep it deterministic
+        keys.sort_by_key(|(k, _)| *k);
+        for (k, v) in keys {
+            wrapper.index.insert(k, wrapper.entries.len());
+            wrapper.entries.push((k, Value::wrap(v.clone())));
+        }
+        wrapper.names = debug_symbols(&object).into_iter()
+            .filter(|(k, _)| wrapper.index.contains_key(k))
+            .filter_map(|(k, v)| CString::new(v).ok().map(|v| (k, v)))
+            .collect();
+        wrapper
+    }
+
+    //Entries in encoding order: insertion order, or ascending hash for canonical encoding
+    fn ordered_entries(&self, options: &EncodeOptions) -> Vec<(u64, &Value)> {
+        let mut entries: Vec<(u64, &Value)> = self.entries.iter().map(|(k, v)| (*k, v)).collect();
+        if options.canonical {
+            entries.sort_by_key(|(k, _)| *k);
         }
-        Self(map)
+        entries
     }
 
-    pub unsafe fn to_object(&self) -> bpx::sd::Object {
-        let mut obj = bpx::sd::Object::with_capacity(self.0.len() as _);
-        for (k, v) in &self.0 {
-            obj.set(Name::from(*k), v.into_value());
+    //Names of the given keys to store as debug symbols, in the same order
+    fn symbols(&self, entries: &[(u64, &Value)], options: &EncodeOptions) -> Vec<&CString> {
+        match options.debug_symbols {
+            true => entries.iter().filter_map(|(k, _)| self.names.get(k)).collect(),
+            false => Vec::new()
+        }
+    }
+
+    pub unsafe fn to_object(&self, options: &EncodeOptions) -> bpx::sd::Object {
+        let entries = self.ordered_entries(options);
+        let mut obj = bpx::sd::Object::with_capacity(self.entries.len() as _);
+        for (k, v) in &entries {
+            obj.set(Name::from(*k), v.into_value_with(options));
+        }
+        let symbols = self.symbols(&entries, options);
+        if symbols.len() > 0 {
+            let mut array = bpx::sd::Array::new();
+            for name in symbols {
+                array.as_mut().push(name.to_string_lossy().into_owned().into());
+            }
+            obj.set(Name::from(bpx::utils::hash(DEBUG_KEY)), array.into());
         }
         obj
     }
+
+    //Writes the entries in encoding order, the debug symbols are written last unless canonical encoding sorts them
+    // along the other keys
+    pub unsafe fn write<W: Write>(&self, out: &mut W, options: &EncodeOptions, max_depth: usize) -> Result<(), c_uint> {
+        let max_depth = enter(max_depth)?;
+        let entries = self.ordered_entries(options);
+        //The symbol table is an entry of the object itself so it is left out when there is no room for it
+        let symbols = match entries.len() < 255 {
+            true => self.symbols(&entries, options),
+            false => Vec::new()
+        };
+        let debug_key = bpx::utils::hash(DEBUG_KEY);
+        write_count(out, entries.len() + (symbols.len() > 0) as usize)?;
+        let mut pending_symbols = symbols.len() > 0;
+        for (k, v) in entries {
+            if pending_symbols && options.canonical && debug_key < k {
+                write_symbols(out, debug_key, &symbols, max_depth)?;
+                pending_symbols = false;
+            }
+            write_bytes(out, &k.to_le_bytes())?;
+            write_bytes(out, &[v.type_code()])?;
+            v.write(out, options, max_depth)?;
+        }
+        if pending_symbols {
+            write_symbols(out, debug_key, &symbols, max_depth)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_symbols<W: Write>(out: &mut W, debug_key: u64, symbols: &[&CString], max_depth: usize) -> Result<(), c_uint> {
+    write_bytes(out, &debug_key.to_le_bytes())?;
+    write_bytes(out, &[ValueType::Array as u8])?;
+    enter(max_depth)?;
+    write_count(out, symbols.len())?;
+    for name in symbols {
+        write_bytes(out, &[ValueType::String as u8])?;
+        write_bytes(out, name.as_bytes_with_nul())?;
+    }
+    Ok(())
 }
 
 #[repr(C)]
 pub struct ObjectEntry {
     hash: u64,
-    value: Value
+    value: Value,
+    //Null when the key name is unknown, valid as long as the entry remains in the object
+    name: *const c_char
 }
 
 export!
@@ -87,42 +221,48 @@ export!
         let len = libc::strlen(key);
         let bytes = std::slice::from_raw_parts(std::mem::transmute(key), len);
         let key = std::str::from_utf8_unchecked(bytes);
-        (*object).0.get(&bpx::utils::hash(key)).cloned().unwrap_or(Value::null())
+        (*object).get(bpx::utils::hash(key)).cloned().unwrap_or(Value::null())
     }
 
     fn bpx_sd_object_rawget(object: *const ObjectWrapper, hash: u64) -> Value
     {
-        (*object).0.get(&hash).cloned().unwrap_or(Value::null())
+        (*object).get(hash).cloned().unwrap_or(Value::null())
     }
 
-    fn bpx_sd_object_set(object: *mut ObjectWrapper, key: *const c_char, value: *mut Value)
+    fn bpx_sd_object_set(object: *mut ObjectWrapper, key: *const c_char, value: *mut Value) -> c_uint
     {
         let len = libc::strlen(key);
         let bytes = std::slice::from_raw_parts(std::mem::transmute(key), len);
         let key = std::str::from_utf8_unchecked(bytes);
-        (*object).insert_or_replace(bpx::utils::hash(key), *value);
+        let hash = bpx::utils::hash(key);
+        (*object).insert_or_replace(hash, *value);
+        (*object).names.entry(hash).or_insert_with(|| CString::new(key).unwrap_or_default());
         (*value).reset();
+        ERR_NONE
     }
 
-    fn bpx_sd_object_rawset(object: *mut ObjectWrapper, hash: u64, value: *mut Value)
+    fn bpx_sd_object_rawset(object: *mut ObjectWrapper, hash: u64, value: *mut Value) -> c_uint
     {
         (*object).insert_or_replace(hash, *value);
         (*value).reset();
+        ERR_NONE
     }
 
     fn bpx_sd_object_len(object: *const ObjectWrapper) -> usize
     {
-        (*object).0.len()
+        (*object).entries.len()
     }
 
-    fn bpx_sd_object_list(object: *const ObjectWrapper, out: *mut ObjectEntry)
+    fn bpx_sd_object_list(object: *const ObjectWrapper, out: *mut ObjectEntry) -> c_uint
     {
-        let slice: &mut [MaybeUninit<ObjectEntry>] = std::slice::from_raw_parts_mut(out as _, (*object).0.len());
-        for (i, (k, v)) in (*object).0.iter().enumerate() {
+        let slice: &mut [MaybeUninit<ObjectEntry>] = std::slice::from_raw_parts_mut(out as _, (*object).entries.len());
+        for (i, (k, v)) in (*object).entries.iter().enumerate() {
             slice[i].write(ObjectEntry {
                 hash: *k,
-                value: *v
+                value: *v,
+                name: (*object).names.get(k).map(|v| v.as_ptr()).unwrap_or(std::ptr::null())
             });
         }
+        ERR_NONE
     }
 }
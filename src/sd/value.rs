@@ -27,10 +27,13 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use std::ffi::{CStr, CString};
+use std::io::Write;
 use std::mem::MaybeUninit;
-use std::os::raw::c_char;
-use crate::ffi_helper::export;
+use std::os::raw::{c_char, c_uint};
+use crate::error_codes::ERR_NONE;
+use crate::ffi_helper::{export, PanicDefault};
 use crate::sd::array::ArrayWrapper;
+use crate::sd::io::write_bytes;
 use crate::sd::object::ObjectWrapper;
 
 #[repr(C)]
@@ -72,6 +75,34 @@ pub union ValueData {
     as_object: *mut ObjectWrapper
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct EncodeOptions {
+    //Stores the names of keys set through bpx_sd_object_set in the "__debug__" array of their object
+    pub debug_symbols: bool,
+    //Writes object keys sorted by hash so that equal values always encode to the same bytes
+    pub canonical: bool
+}
+
+//Borrowed view of a value, used to walk a value tree without converting it to a bpx::sd::Value
+pub enum ValueRef<'a> {
+    Null,
+    Bool(bool),
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    Uint64(u64),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    String(&'a str),
+    Array(&'a ArrayWrapper),
+    Object(&'a ObjectWrapper)
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Value {
@@ -97,6 +128,14 @@ impl Value {
         }
     }
 
+    pub fn array(array: ArrayWrapper) -> Self {
+        Self::new(ValueType::Array, ValueData { as_array: array.into_raw() })
+    }
+
+    pub fn object(object: ObjectWrapper) -> Self {
+        Self::new(ValueType::Object, ValueData { as_object: object.into_raw() })
+    }
+
     pub fn wrap(value: bpx::sd::Value) -> Self {
         match value {
             bpx::sd::Value::Null => Self::null(),
@@ -112,12 +151,16 @@ impl Value {
             bpx::sd::Value::Float(v) => Self::new(ValueType::Float, ValueData { as_float: v }),
             bpx::sd::Value::Double(v) => Self::new(ValueType::Double, ValueData { as_double: v }),
             bpx::sd::Value::String(v) => Self::new(ValueType::String, ValueData { as_string: CString::new(v).unwrap().into_raw() }),
-            bpx::sd::Value::Array(v) => Self::new(ValueType::Array, ValueData { as_array: ArrayWrapper::wrap(v).into_raw() }),
-            bpx::sd::Value::Object(v) => Self::new(ValueType::Object, ValueData { as_object: ObjectWrapper::wrap(v).into_raw() })
+            bpx::sd::Value::Array(v) => Self::array(ArrayWrapper::wrap(v)),
+            bpx::sd::Value::Object(v) => Self::object(ObjectWrapper::wrap(v))
         }
     }
 
     pub unsafe fn into_value(self) -> bpx::sd::Value {
+        self.into_value_with(&EncodeOptions::default())
+    }
+
+    pub unsafe fn into_value_with(self, options: &EncodeOptions) -> bpx::sd::Value {
         match self.ty {
             ValueType::Null => bpx::sd::Value::Null,
             ValueType::Bool => self.data.assume_init().as_bool.into(),
@@ -137,11 +180,60 @@ impl Value {
                 let s = String::from(std::str::from_utf8_unchecked(slice));
                 s.into()
             }
-            ValueType::Array => (*self.data.assume_init().as_array).to_array().into(),
-            ValueType::Object => (*self.data.assume_init().as_object).to_object().into()
+            ValueType::Array => (*self.data.assume_init().as_array).to_array(options).into(),
+            ValueType::Object => (*self.data.assume_init().as_object).to_object(options).into()
+        }
+    }
+
+    pub unsafe fn view<'a>(&self) -> ValueRef<'a> {
+        match self.ty {
+            ValueType::Null => ValueRef::Null,
+            ValueType::Bool => ValueRef::Bool(self.data.assume_init().as_bool),
+            ValueType::Uint8 => ValueRef::Uint8(self.data.assume_init().as_u8),
+            ValueType::Uint16 => ValueRef::Uint16(self.data.assume_init().as_u16),
+            ValueType::Uint32 => ValueRef::Uint32(self.data.assume_init().as_u32),
+            ValueType::Uint64 => ValueRef::Uint64(self.data.assume_init().as_u64),
+            ValueType::Int8 => ValueRef::Int8(self.data.assume_init().as_i8),
+            ValueType::Int16 => ValueRef::Int16(self.data.assume_init().as_i16),
+            ValueType::Int32 => ValueRef::Int32(self.data.assume_init().as_i32),
+            ValueType::Int64 => ValueRef::Int64(self.data.assume_init().as_i64),
+            ValueType::Float => ValueRef::Float(self.data.assume_init().as_float),
+            ValueType::Double => ValueRef::Double(self.data.assume_init().as_double),
+            ValueType::String => {
+                let bytes = CStr::from_ptr(self.data.assume_init().as_string).to_bytes();
+                ValueRef::String(std::str::from_utf8_unchecked(bytes))
+            },
+            ValueType::Array => ValueRef::Array(&*self.data.assume_init().as_array),
+            ValueType::Object => ValueRef::Object(&*self.data.assume_init().as_object)
+        }
+    }
+
+    //Same encoding as bpx::sd::Value::write, written straight from this value so that objects keep their key order
+    pub unsafe fn write<W: Write>(&self, out: &mut W, options: &EncodeOptions, max_depth: usize) -> Result<(), c_uint> {
+        match self.ty {
+            ValueType::Null => Ok(()),
+            ValueType::Bool => write_bytes(out, &[self.data.assume_init().as_bool as u8]),
+            ValueType::Uint8 => write_bytes(out, &[self.data.assume_init().as_u8]),
+            ValueType::Uint16 => write_bytes(out, &self.data.assume_init().as_u16.to_le_bytes()),
+            ValueType::Uint32 => write_bytes(out, &self.data.assume_init().as_u32.to_le_bytes()),
+            ValueType::Uint64 => write_bytes(out, &self.data.assume_init().as_u64.to_le_bytes()),
+            ValueType::Int8 => write_bytes(out, &[self.data.assume_init().as_i8 as u8]),
+            ValueType::Int16 => write_bytes(out, &self.data.assume_init().as_i16.to_le_bytes()),
+            ValueType::Int32 => write_bytes(out, &self.data.assume_init().as_i32.to_le_bytes()),
+            ValueType::Int64 => write_bytes(out, &self.data.assume_init().as_i64.to_le_bytes()),
+            ValueType::Float => write_bytes(out, &self.data.assume_init().as_float.to_le_bytes()),
+            ValueType::Double => write_bytes(out, &self.data.assume_init().as_double.to_le_bytes()),
+            ValueType::String => write_bytes(out, CStr::from_ptr(self.data.assume_init().as_string).to_bytes_with_nul()),
+            ValueType::Array => (*self.data.assume_init().as_array).write(out, options, max_depth),
+            ValueType::Object => (*self.data.assume_init().as_object).write(out, options, max_depth)
         }
     }
 
+    //BPXSD type code, which is also the position of the type in ValueType
+    pub fn type_code(&self) -> u8 {
+        self.ty as u8
+    }
+
     pub fn null() -> Self {
         Value {
             ty: ValueType::Null,
@@ -170,6 +262,12 @@ impl Value {
     }
 }
 
+impl PanicDefault for Value {
+    fn panic_default() -> Self {
+        Value::null()
+    }
+}
+
 export!
 {
     fn bpx_sd_value_new() -> Value
@@ -239,16 +337,251 @@ export!
 
     fn bpx_sd_value_new_array() -> Value
     {
-        Value::new(ValueType::Array, ValueData { as_array: ArrayWrapper::new().into_raw() })
+        Value::array(ArrayWrapper::new())
     }
 
     fn bpx_sd_value_new_object() -> Value
     {
-        Value::new(ValueType::Object, ValueData { as_object: ObjectWrapper::new().into_raw() })
+        Value::object(ObjectWrapper::new())
     }
 
-    fn bpx_sd_value_free(value: *mut Value)
+    fn bpx_sd_value_free(value: *mut Value) -> c_uint
     {
         (*value).free();
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use crate::error_codes::ERR_SD_MAX_DEPTH_EXCEEDED;
+    use crate::sd::array::{bpx_sd_array_push, ArrayWrapper};
+    use crate::sd::io::MAX_DEPTH;
+    use crate::sd::object::{bpx_sd_object_set, debug_symbols, ObjectWrapper, DEBUG_KEY};
+    use super::*;
+
+    const TYPE_UINT8: u8 = ValueType::Uint8 as u8;
+    const TYPE_STRING: u8 = ValueType::String as u8;
+    const TYPE_ARRAY: u8 = ValueType::Array as u8;
+
+    fn scalars() -> Vec<bpx::sd::Value> {
+        vec![
+            bpx::sd::Value::Null,
+            true.into(),
+            200u8.into(),
+            60000u16.into(),
+            u32::MAX.into(),
+            u64::MAX.into(),
+            (-100i8).into(),
+            i16::MIN.into(),
+            i32::MIN.into(),
+            i64::MIN.into(),
+            1.5f32.into(),
+            f64::NAN.into(),
+            String::from("string").into()
+        ]
+    }
+
+    fn single(value: bpx::sd::Value) -> bpx::sd::Value {
+        let mut obj = bpx::sd::Object::new();
+        obj.set("v", value);
+        obj.into()
+    }
+
+    fn encode(value: &Value, options: EncodeOptions) -> Result<Vec<u8>, c_uint> {
+        let mut out = Vec::new();
+        unsafe { value.write(&mut out, &options, MAX_DEPTH) }.map(|_| out)
+    }
+
+    fn bpx_encode(value: &bpx::sd::Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        value.write(&mut out).unwrap();
+        out
+    }
+
+    fn decode(data: &[u8]) -> bpx::sd::Object {
+        match bpx::sd::Value::read(data).unwrap() {
+            bpx::sd::Value::Object(v) => v,
+            _ => panic!("expected an object")
+        }
+    }
+
+    //Keys of an encoded object whose values are u8 or arrays of strings
+    fn keys(data: &[u8]) -> Vec<u64> {
+        let mut keys = Vec::new();
+        let mut pos = 1;
+        for _ in 0..data[0] {
+            keys.push(u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap()));
+            let ty = data[pos + 8];
+            pos += 9;
+            match ty {
+                TYPE_UINT8 => pos += 1,
+                TYPE_ARRAY => {
+                    let count = data[pos];
+                    pos += 1;
+                    for _ in 0..count {
+                        assert_eq!(data[pos], TYPE_STRING);
+                        pos += data[pos..].iter().position(|v| *v == 0).unwrap() + 1;
+                    }
+                },
+                _ => panic!("unexpected type {}", ty)
+            }
+        }
+        assert_eq!(pos, data.len());
+        keys
+    }
+
+    unsafe fn object(entries: &[(&str, Value)]) -> Value {
+        let obj = ObjectWrapper::new().into_raw();
+        for (k, v) in entries {
+            let key = CString::new(*k).unwrap();
+            let mut v = *v;
+            bpx_sd_object_set(obj, key.as_ptr(), &mut v);
+        }
+        Value::new(ValueType::Object, ValueData { as_object: obj })
+    }
+
+    unsafe fn nested_arrays(depth: usize) -> Value {
+        let mut value = Value::null();
+        for _ in 0..depth {
+            let array = ArrayWrapper::new().into_raw();
+            bpx_sd_array_push(array, &mut value);
+            value = Value::new(ValueType::Array, ValueData { as_array: array });
+        }
+        value
+    }
+
+    fn u8_value(v: u8) -> Value {
+        Value::new(ValueType::Uint8, ValueData { as_u8: v })
+    }
+
+    #[test]
+    fn same_bytes_as_bpx() {
+        let mut values = scalars();
+        let mut array = bpx::sd::Array::new();
+        for v in scalars() {
+            array.as_mut().push(v);
+        }
+        let mut nested = bpx::sd::Array::new();
+        nested.as_mut().push(array.clone().into());
+        nested.as_mut().push(bpx::sd::Array::new().into());
+        values.push(array.into());
+        values.push(nested.into());
+        values.push(single(single(single(1u8.into()))));
+        for v in values {
+            let expected = bpx_encode(&single(v.clone()));
+            let mut value = Value::wrap(single(v));
+            assert_eq!(encode(&value, EncodeOptions::default()).unwrap(), expected);
+            unsafe { value.free() };
+        }
+    }
+
+    #[test]
+    fn insertion_order() {
+        unsafe {
+            let mut value = object(&[("c", u8_value(1)), ("a", u8_value(2)), ("b", u8_value(3))]);
+            let data = encode(&value, EncodeOptions::default()).unwrap();
+            assert_eq!(keys(&data), [bpx::utils::hash("c"), bpx::utils::hash("a"), bpx::utils::hash("b")]);
+            let obj = decode(&data);
+            assert!(matches!(obj.get("c"), Some(bpx::sd::Value::Uint8(1))));
+            assert!(matches!(obj.get("a"), Some(bpx::sd::Value::Uint8(2))));
+            assert!(matches!(obj.get("b"), Some(bpx::sd::Value::Uint8(3))));
+            assert!(obj.get(DEBUG_KEY).is_none());
+            value.free();
+        }
+    }
+
+    #[test]
+    fn canonical() {
+        let options = EncodeOptions {
+            debug_symbols: false,
+            canonical: true
+        };
+        unsafe {
+            let mut a = object(&[("c", u8_value(1)), ("a", u8_value(2)), ("b", u8_value(3))]);
+            let mut b = object(&[("b", u8_value(3)), ("c", u8_value(1)), ("a", u8_value(2))]);
+            let data = encode(&a, options).unwrap();
+            assert_eq!(data, encode(&b, options).unwrap());
+            let keys = keys(&data);
+            assert_eq!(keys.len(), 3);
+            assert!(keys.windows(2).all(|v| v[0] < v[1]));
+            assert!(matches!(decode(&data).get("a"), Some(bpx::sd::Value::Uint8(2))));
+            a.free();
+            b.free();
+        }
+    }
+
+    #[test]
+    fn debug_symbols_round_trip() {
+        let debug_key = bpx::utils::hash(DEBUG_KEY);
+        unsafe {
+            let mut value = object(&[("b", u8_value(1)), ("a", u8_value(2))]);
+            for canonical in [false, true] {
+                let data = encode(&value, EncodeOptions {
+                    debug_symbols: true,
+                    canonical
+                }).unwrap();
+                let keys = keys(&data);
+                assert_eq!(keys.len(), 3);
+                match canonical {
+                    false => assert_eq!(keys[2], debug_key),
+                    true => assert!(keys.windows(2).all(|v| v[0] < v[1]))
+                }
+                let obj = decode(&data);
+                let names = debug_symbols(&obj);
+                assert_eq!(names.len(), 2);
+                assert_eq!(names[&bpx::utils::hash("a")], "a");
+                assert_eq!(names[&bpx::utils::hash("b")], "b");
+                //Decoding the symbols back gives the same encoding
+                let mut decoded = Value::wrap(obj.into());
+                let options = EncodeOptions {
+                    debug_symbols: true,
+                    canonical: true
+                };
+                assert_eq!(encode(&decoded, options).unwrap(), encode(&value, options).unwrap());
+                decoded.free();
+            }
+            value.free();
+        }
+    }
+
+    #[test]
+    fn entry_limit() {
+        let options = EncodeOptions {
+            debug_symbols: true,
+            canonical: false
+        };
+        unsafe {
+            let entries: Vec<(String, Value)> = (0..256).map(|i| (format!("key{}", i), u8_value(i as u8))).collect();
+            let entries: Vec<(&str, Value)> = entries.iter().map(|(k, v)| (&**k, *v)).collect();
+            //The symbol table takes the last entry
+            let mut value = object(&entries[..254]);
+            let obj = decode(&encode(&value, options).unwrap());
+            assert_eq!(debug_symbols(&obj).len(), 254);
+            value.free();
+            //No room left for it
+            let mut value = object(&entries[..255]);
+            let obj = decode(&encode(&value, options).unwrap());
+            assert!(obj.get(DEBUG_KEY).is_none());
+            assert!(matches!(obj.get("key254"), Some(bpx::sd::Value::Uint8(254))));
+            value.free();
+            let mut value = object(&entries);
+            assert!(encode(&value, options).is_err());
+            value.free();
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        unsafe {
+            //The root object is the first level
+            let mut value = object(&[("v", nested_arrays(MAX_DEPTH - 2))]);
+            assert!(encode(&value, EncodeOptions::default()).is_ok());
+            value.free();
+            let mut value = object(&[("v", nested_arrays(MAX_DEPTH - 1))]);
+            assert_eq!(encode(&value, EncodeOptions::default()).err(), Some(ERR_SD_MAX_DEPTH_EXCEEDED));
+            value.free();
+        }
     }
 }
@@ -30,11 +30,14 @@ use std::io::{Read, SeekFrom, Seek, Write};
 use std::os::raw::{c_int, c_uint};
 use crate::types::{Container, Handle, Section};
 use crate::error_codes::unwrap_or_err;
-use crate::error_codes::{CErrCode, ERR_NONE};
+use crate::error_codes::{CErrCode, ERR_CORE_IO, ERR_NONE, ERR_NOT_MMAP_BACKED, ERR_SECTION_COMPRESSED, ERR_SECTION_OUT_OF_RANGE};
+use crate::last_error::LastError;
+use crate::container::{COMPRESSION_XZ, COMPRESSION_ZLIB};
 use crate::types::SectionHeader;
 use crate::ffi_helper::export;
 use crate::ffi_helper::export_object;
 use crate::ffi_helper::OutCell;
+use crate::io_wrapper;
 use crate::ffi_helper::Object;
 use bpx::core::SectionData;
 use bpx::traits::Shift;
@@ -42,8 +45,9 @@ use bpx::traits::ShiftTo;
 
 export_object! {
     Container {
-        fn bpx_section_get_header(this, handle: Handle, section_header: OutCell<SectionHeader>) {
-            let section = this.sections().header(bpx::core::Handle::from_raw(handle));
+        fn bpx_section_get_header(this, handle: Handle, section_header: OutCell<SectionHeader>) -> c_uint {
+            let handle = unwrap_or_err!(this.handle(handle));
+            let section = this.section_header(handle);
             section_header.set(SectionHeader {
                 size: section.size,
                 chksum: section.chksum,
@@ -52,20 +56,46 @@ export_object! {
                 ty: section.ty,
                 pointer: section.pointer
             });
+            ERR_NONE
         }
 
         fn bpx_section_load(this, handle: Handle, out: OutCell<Object<Section>>) -> c_uint {
-            let section = unwrap_or_err!(this.sections()
-                .load(bpx::core::Handle::from_raw(handle)).map_err(|e| e.cerr_code()));
-            out.set(Object::new(section));
+            let handle = unwrap_or_err!(this.handle(handle));
+            unwrap_or_err!(this.decode_raw(handle));
+            let section = unwrap_or_err!(this.sections().load(handle).map_err(|e| e.cerr_code()));
+            out.set(Object::new(this.open_section(handle, section)));
             ERR_NONE
         }
 
         fn bpx_section_open(this, handle: Handle, out: OutCell<Object<Section>>) -> c_uint
         {
-            let section = unwrap_or_err!(this.sections()
-                .open(bpx::core::Handle::from_raw(handle)).map_err(|e| e.cerr_code()));
-            out.set(Object::new(section));
+            let handle = unwrap_or_err!(this.handle(handle));
+            unwrap_or_err!(this.decode_raw(handle));
+            let section = unwrap_or_err!(this.sections().open(handle).map_err(|e| e.cerr_code()));
+            out.set(Object::new(this.open_section(handle, section)));
+            ERR_NONE
+        }
+
+        //Borrows the section data straight from the file mapping, only valid until the container is closed.
+        fn bpx_section_load_mapped(this, handle: Handle, data: OutCell<*const u8>, size: OutCell<usize>) -> c_uint
+        {
+            let mapping = unwrap_or_err!(this.mapping().ok_or_else(|| {
+                LastError::new(ERR_NOT_MMAP_BACKED, "container is not backed by a file mapping").set()
+            }));
+            let handle = unwrap_or_err!(this.handle(handle));
+            let header = this.sections().header(handle);
+            if header.flags & (COMPRESSION_ZLIB | COMPRESSION_XZ) != 0 {
+                return LastError::new(ERR_SECTION_COMPRESSED, format!("section {} is compressed", this.export_handle(handle))).set();
+            }
+            //The header comes from the file and may point anywhere
+            let range = usize::try_from(header.pointer).ok()
+                .and_then(|start| start.checked_add(header.size as usize).map(|end| start..end));
+            let slice = unwrap_or_err!(range.and_then(|range| mapping.as_slice().get(range)).ok_or_else(|| {
+                LastError::new(ERR_CORE_IO, format!("section {} is out of the bounds of the file mapping ({} + {} > {})",
+                    this.export_handle(handle), header.pointer, header.size, mapping.as_slice().len())).set()
+            }));
+            data.set(slice.as_ptr());
+            size.set(slice.len());
             ERR_NONE
         }
     }
@@ -83,12 +113,18 @@ export_object! {
 
         //SAFETY: make sure buffer is initialized otherwise UB!
         mut fn bpx_section_write(this, buffer: *const u8, size: usize) -> usize {
+            if this.check_writable().is_err() {
+                return usize::MAX;
+            }
             let slice = std::slice::from_raw_parts(buffer, size);
             this.write(slice).unwrap_or(usize::MAX)
         }
 
         //SAFETY: make sure buffer is initialized otherwise UB!
         mut fn bpx_section_write_append(this, buffer: *const u8, size: usize) -> usize {
+            if this.check_writable().is_err() {
+                return usize::MAX;
+            }
             let slice = std::slice::from_raw_parts(buffer, size);
             this.write_append(slice).unwrap_or(usize::MAX)
         }
@@ -100,12 +136,18 @@ export_object! {
         mut fn bpx_section_flush(this) -> c_int { this.flush().map(|_| 0).unwrap_or(-1) }
 
         mut fn bpx_section_truncate(this, size: usize, new_size: OutCell<usize>) -> c_int {
+            if this.check_writable().is_err() {
+                return -1;
+            }
             let size = unwrap_or_err!(this.truncate(size).map_err(|_| -1));
             new_size.set(size);
             0
         }
 
         mut fn bpx_section_shift(this, amount: i64) -> c_int {
+            if this.check_writable().is_err() {
+                return -1;
+            }
             let res = if amount < 0 {
                 this.shift(ShiftTo::Left(-amount as u64))
             } else {
@@ -114,6 +156,151 @@ export_object! {
             res.map(|_| 0).unwrap_or(-1)
         }
 
+        mut fn bpx_section_read2(this, buffer: *mut u8, size: usize, bytes_read: OutCell<usize>) -> c_uint {
+            std::ptr::write_bytes(buffer, 0, size); //This allows us to initialize the buffer in preparation of std::io::Read call
+            let slice = std::slice::from_raw_parts_mut(buffer, size);
+            let len = unwrap_or_err!(this.read(slice).map_err(|e| e.cerr_code()));
+            bytes_read.set(len);
+            ERR_NONE
+        }
+
+        //SAFETY: make sure buffer is initialized otherwise UB!
+        mut fn bpx_section_write2(this, buffer: *const u8, size: usize, bytes_written: OutCell<usize>) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            let slice = std::slice::from_raw_parts(buffer, size);
+            let len = unwrap_or_err!(this.write(slice).map_err(|e| e.cerr_code()));
+            bytes_written.set(len);
+            ERR_NONE
+        }
+
+        //SAFETY: make sure buffer is initialized otherwise UB!
+        mut fn bpx_section_write_append2(this, buffer: *const u8, size: usize, bytes_written: OutCell<usize>) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            let slice = std::slice::from_raw_parts(buffer, size);
+            let len = unwrap_or_err!(this.write_append(slice).map_err(|e| e.cerr_code()));
+            bytes_written.set(len);
+            ERR_NONE
+        }
+
+        mut fn bpx_section_seek2(this, whence: io_wrapper::SeekFrom, offset: i64, new_pos: OutCell<u64>) -> c_uint {
+            let size = this.size() as u64;
+            let base = match whence {
+                io_wrapper::SeekFrom::Start => 0,
+                io_wrapper::SeekFrom::End => size,
+                io_wrapper::SeekFrom::Current => unwrap_or_err!(this.stream_position().map_err(|e| e.cerr_code()))
+            };
+            let pos = unwrap_or_err!(base.checked_add_signed(offset).filter(|v| *v <= size).ok_or_else(|| {
+                LastError::new(ERR_SECTION_OUT_OF_RANGE, format!("seek out of range ({} + {} not in 0..={})", base, offset, size)).set()
+            }));
+            let pos = unwrap_or_err!(this.seek(SeekFrom::Start(pos)).map_err(|e| e.cerr_code()));
+            new_pos.set(pos);
+            ERR_NONE
+        }
+
+        mut fn bpx_section_tell(this, pos: OutCell<u64>) -> c_uint {
+            let cur = unwrap_or_err!(this.stream_position().map_err(|e| e.cerr_code()));
+            pos.set(cur);
+            ERR_NONE
+        }
+
+        mut fn bpx_section_flush2(this) -> c_uint {
+            unwrap_or_err!(this.flush().map_err(|e| e.cerr_code()));
+            ERR_NONE
+        }
+
+        mut fn bpx_section_truncate2(this, size: usize, new_size: OutCell<usize>) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            let size = unwrap_or_err!(this.truncate(size).map_err(|e| e.cerr_code()));
+            new_size.set(size);
+            ERR_NONE
+        }
+
+        mut fn bpx_section_shift2(this, amount: i64) -> c_uint {
+            unwrap_or_err!(this.check_writable());
+            let res = if amount < 0 {
+                this.shift(ShiftTo::Left(-amount as u64))
+            } else {
+                this.shift(ShiftTo::Right(amount as u64))
+            };
+            unwrap_or_err!(res.map_err(|e| e.cerr_code()));
+            ERR_NONE
+        }
+
         close bpx_section_close(this) {}
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests
+{
+    use std::fs::File;
+    use std::rc::Rc;
+    use crate::container::CHECKSUM_CRC32;
+    use crate::container_wrapper::ContainerWrapper;
+    use crate::mmap_io::{Mapping, MmapIo};
+    use crate::open::open_container;
+    use crate::test_utils;
+    use super::*;
+
+    //Writes a container with a zlib section followed by an uncompressed section, the last truncate bytes of the
+    // file are cut off, then maps it
+    fn mapped(name: &str, truncate: usize) -> (Container, Vec<u8>)
+    {
+        let mut container = test_utils::create();
+        let content = test_utils::content(1024, 4);
+        test_utils::add_section(&mut container, COMPRESSION_ZLIB, &test_utils::content(4096, 3));
+        test_utils::add_section(&mut container, CHECKSUM_CRC32, &content);
+        container.save_changes().unwrap();
+        let data = test_utils::into_bytes(container);
+        let path = std::env::temp_dir().join(format!("bpx-c-{}-{}.bpx", std::process::id(), name));
+        std::fs::write(&path, &data[..data.len() - truncate]).unwrap();
+        let f = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mapping = Rc::new(Mapping::new(&f).unwrap());
+        let container = open_container(ContainerWrapper::from(MmapIo::new(mapping.clone())), true).unwrap();
+        (container.with_mapping(mapping), content)
+    }
+
+    unsafe fn load_mapped(container: &Container, index: u32) -> Result<&[u8], c_uint>
+    {
+        let handle = container.export_handle(container.sections().find_by_index(index).unwrap());
+        let mut data: *const u8 = std::ptr::null();
+        let mut size: usize = 0;
+        match bpx_section_load_mapped(container, handle, std::mem::transmute(&mut data), std::mem::transmute(&mut size)) {
+            ERR_NONE => Ok(std::slice::from_raw_parts(data, size)),
+            code => Err(code)
+        }
+    }
+
+    #[test]
+    fn load_mapped_section()
+    {
+        let (container, content) = mapped("mapped", 0);
+        unsafe {
+            assert_eq!(load_mapped(&container, 1), Ok(&content[..]));
+            assert_eq!(load_mapped(&container, 0), Err(ERR_SECTION_COMPRESSED));
+        }
+        //Loading through BPX still works
+        assert_eq!(test_utils::read(&container, container.sections().find_by_index(1).unwrap()), content);
+    }
+
+    #[test]
+    fn load_mapped_out_of_bounds()
+    {
+        let (container, _) = mapped("mapped-truncated", 16);
+        unsafe {
+            assert_eq!(load_mapped(&container, 1), Err(ERR_CORE_IO));
+        }
+    }
+
+    #[test]
+    fn load_mapped_without_mapping()
+    {
+        let mut container = test_utils::create();
+        test_utils::add_section(&mut container, 0, &test_utils::content(64, 1));
+        let container = test_utils::reopen(container);
+        unsafe {
+            assert_eq!(load_mapped(&container, 0), Err(ERR_NOT_MMAP_BACKED));
+        }
+    }
+}
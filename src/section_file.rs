@@ -0,0 +1,154 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::ffi::{c_void, CStr};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_int};
+use libc::{off64_t, size_t, ssize_t};
+use bpx::core::SectionData;
+use crate::error_codes::{CErrCode, ERR_SECTION_IO};
+use crate::ffi_helper::callback;
+use crate::ffi_helper::export;
+use crate::last_error::LastError;
+use crate::types::Section;
+
+#[repr(C)]
+struct CookieIoFunctions
+{
+    read: Option<callback!((cookie: *mut c_void, buffer: *mut c_char, size: size_t) -> ssize_t)>,
+    write: Option<callback!((cookie: *mut c_void, buffer: *const c_char, size: size_t) -> ssize_t)>,
+    seek: Option<callback!((cookie: *mut c_void, offset: *mut off64_t, whence: c_int) -> c_int)>,
+    close: Option<callback!((cookie: *mut c_void) -> c_int)>
+}
+
+extern "C" {
+    fn fopencookie(cookie: *mut c_void, mode: *const c_char, functions: CookieIoFunctions) -> *mut libc::FILE;
+}
+
+//Errors are reported through both errno (for stdio) and the last error (for bpx_get_last_error)
+fn io_error(e: std::io::Error) -> c_int
+{
+    e.cerr_code();
+    unsafe {
+        *libc::__errno_location() = e.raw_os_error().unwrap_or(libc::EIO);
+    }
+    -1
+}
+
+//Stdio callbacks must not unwind into C
+fn guard<R>(err: R, f: impl FnOnce() -> R) -> R
+{
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or(err)
+}
+
+unsafe extern "C" fn read(cookie: *mut c_void, buffer: *mut c_char, size: size_t) -> ssize_t
+{
+    guard(-1, || {
+        let section = &mut *(cookie as *mut Section);
+        let slice = std::slice::from_raw_parts_mut(buffer as *mut u8, size);
+        match section.read(slice) {
+            Ok(len) => len as ssize_t,
+            Err(e) => io_error(e) as ssize_t
+        }
+    })
+}
+
+unsafe extern "C" fn write(cookie: *mut c_void, buffer: *const c_char, size: size_t) -> ssize_t
+{
+    guard(-1, || {
+        let section = &mut *(cookie as *mut Section);
+        let slice = std::slice::from_raw_parts(buffer as *const u8, size);
+        match section.write(slice) {
+            Ok(len) => len as ssize_t,
+            Err(e) => io_error(e) as ssize_t
+        }
+    })
+}
+
+unsafe extern "C" fn seek(cookie: *mut c_void, offset: *mut off64_t, whence: c_int) -> c_int
+{
+    guard(-1, || {
+        let section = &mut *(cookie as *mut Section);
+        let pos = match whence {
+            libc::SEEK_SET if *offset >= 0 => SeekFrom::Start(*offset as u64),
+            libc::SEEK_CUR => SeekFrom::Current(*offset),
+            libc::SEEK_END => SeekFrom::End(*offset),
+            _ => return io_error(std::io::Error::from_raw_os_error(libc::EINVAL))
+        };
+        match section.seek(pos) {
+            Ok(pos) => {
+                *offset = pos as off64_t;
+                0
+            },
+            Err(e) => io_error(e)
+        }
+    })
+}
+
+unsafe extern "C" fn close(cookie: *mut c_void) -> c_int
+{
+    guard(-1, || {
+        let section = &mut *(cookie as *mut Section);
+        match section.flush() {
+            Ok(()) => 0,
+            Err(e) => io_error(e)
+        }
+    })
+}
+
+export! {
+    //The stream borrows the section which must stay open until fclose. Mode "w" truncates the section and mode "a"
+    // starts at the end of the section. Returns NULL on failure.
+    fn bpx_section_fopen(section: *mut Section, mode: *const c_char) -> *mut libc::FILE {
+        let this = &mut *section;
+        let mode_str = CStr::from_ptr(mode).to_bytes();
+        if mode_str.iter().any(|v| matches!(v, b'w' | b'a' | b'+')) && this.check_writable().is_err() {
+            return std::ptr::null_mut();
+        }
+        let res = match mode_str.first() {
+            Some(b'w') => this.truncate(0).and_then(|_| this.seek(SeekFrom::Start(0))),
+            Some(b'a') => this.seek(SeekFrom::End(0)),
+            _ => this.seek(SeekFrom::Start(0))
+        };
+        if let Err(e) = res {
+            e.cerr_code();
+            return std::ptr::null_mut();
+        }
+        let file = fopencookie(section as *mut c_void, mode, CookieIoFunctions {
+            read: Some(read),
+            write: Some(write),
+            seek: Some(seek),
+            close: Some(close)
+        });
+        if file.is_null() {
+            let e = std::io::Error::last_os_error();
+            LastError::new(ERR_SECTION_IO, format!("fopencookie failed: {}", e)).io(&e).set();
+        }
+        file
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+
+//In-memory containers shared by the tests of the container level functions
+
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use crate::container::{section_header_builder, SectionOptions, COMPRESSION_THRESHOLD};
+use crate::container_wrapper::ContainerWrapper;
+use crate::memory_io::MemoryIo;
+use crate::open::{create_container, open_container, ContainerOptions};
+use crate::types::Container;
+
+pub fn create() -> Container
+{
+    unsafe {
+        create_container(ContainerWrapper::from(MemoryIo::owned()), &ContainerOptions {
+            ty: b'T',
+            version: 2,
+            type_ext: [0; 16]
+        })
+    }
+}
+
+pub fn open(data: Vec<u8>) -> Container
+{
+    open_container(ContainerWrapper::from(MemoryIo::Owned(Cursor::new(data))), false).unwrap()
+}
+
+//Compressible content which still differs from one section to another
+pub fn content(len: usize, seed: u8) -> Vec<u8>
+{
+    (0..len).map(|i| (i % 13) as u8 ^ seed).collect()
+}
+
+//Compressed sections are compressed whatever their size
+pub fn add_section(container: &mut Container, flags: u8, data: &[u8]) -> bpx::core::Handle
+{
+    let handle = container.create_section(section_header_builder(&SectionOptions {
+        size: data.len() as u32,
+        ty: 1,
+        flags: flags | COMPRESSION_THRESHOLD,
+        threshold: 0
+    }));
+    container.sections().open(handle).unwrap().write_all(data).unwrap();
+    handle
+}
+
+pub fn read(container: &Container, handle: bpx::core::Handle) -> Vec<u8>
+{
+    container.decode_raw(handle).unwrap();
+    let mut data = container.sections().load(handle).unwrap();
+    let mut buf = Vec::new();
+    data.seek(SeekFrom::Start(0)).unwrap();
+    data.read_to_end(&mut buf).unwrap();
+    buf
+}
+
+pub fn into_bytes(container: Container) -> Vec<u8>
+{
+    match container.into_backend() {
+        Some(ContainerWrapper::Memory(v)) => v.into_vec().unwrap(),
+        _ => panic!("container is not backed by an owned buffer")
+    }
+}
+
+//Saves the container then opens it again from the saved bytes so that no section is loaded
+pub fn reopen(mut container: Container) -> Container
+{
+    container.save_changes().unwrap();
+    open(into_bytes(container))
+}
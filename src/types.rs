@@ -26,13 +26,405 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::container_wrapper::ContainerWrapper;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::os::raw::c_uint;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use bpx::core::builder::SectionHeaderBuilder;
+use crate::buffered_io::IoStats;
+use crate::container_wrapper::{ContainerWrapper, SharedWrapper};
+use crate::error_codes::{CErrCode, ERR_INVALID_HANDLE, ERR_NONE, ERR_OPEN_SECTION_IN_USE, ERR_READ_ONLY};
+use crate::last_error::LastError;
+use crate::mmap_io::Mapping;
+use crate::raw_section;
+use crate::raw_section::RawSection;
 
 pub type Handle = u32;
 
-pub type Container = bpx::core::Container<ContainerWrapper>;
+//Handles given to C are drawn from a process wide counter rather than being the ones of BPX, which restart at 1 in
+// every container, so that a handle of another container is rejected instead of designating one of its sections
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
 
-pub type Section = std::cell::RefMut<'static, bpx::core::AutoSectionData>;
+pub struct Container
+{
+    inner: bpx::core::Container<SharedWrapper>,
+    backend: SharedWrapper,
+    read_only: bool,
+    mapping: Option<Rc<Mapping>>,
+    io_error: Option<Rc<Cell<c_uint>>>,
+    io_stats: Option<Rc<Cell<IoStats>>>,
+    open_sections: Rc<Cell<usize>>,
+    //Sections by handle given to C, and the other way around by raw BPX handle
+    handles: HashMap<Handle, bpx::core::Handle>,
+    exported: HashMap<u32, Handle>,
+    //Sections which may differ from their copy in the backend since the last save, shared with open sections which
+    // add themselves when written to
+    modified: Rc<RefCell<HashSet<u32>>>,
+    //False until a created container is first written to its backend
+    saved: bool,
+    //Original headers of the sections holding a compressed payload copied as is (see raw_section)
+    raw_sections: RefCell<HashMap<u32, RawSection>>
+}
+
+impl Container
+{
+    //backend must be the backend inner was created with
+    pub fn new(inner: bpx::core::Container<SharedWrapper>, backend: SharedWrapper, read_only: bool) -> Container
+    {
+        let mut container = Container {
+            inner,
+            backend,
+            read_only,
+            mapping: None,
+            io_error: None,
+            io_stats: None,
+            open_sections: Rc::new(Cell::new(0)),
+            handles: HashMap::new(),
+            exported: HashMap::new(),
+            modified: Rc::new(RefCell::new(HashSet::new())),
+            saved: true,
+            raw_sections: RefCell::new(HashMap::new())
+        };
+        let sections: Vec<bpx::core::Handle> = container.sections().iter().collect();
+        for handle in sections {
+            container.register(handle);
+        }
+        container
+    }
+
+    fn register(&mut self, handle: bpx::core::Handle) -> Handle
+    {
+        let raw = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+        self.handles.insert(raw, handle);
+        self.exported.insert(handle.into_raw(), raw);
+        raw
+    }
+
+    //Marks a container which has just been created and has nothing in its backend yet
+    pub fn created(mut self) -> Container
+    {
+        self.saved = false;
+        self
+    }
+
+    pub fn is_saved(&self) -> bool
+    {
+        self.saved
+    }
+
+    pub fn with_mapping(mut self, mapping: Rc<Mapping>) -> Container
+    {
+        self.mapping = Some(mapping);
+        self
+    }
+
+    pub fn mapping(&self) -> Option<&Mapping>
+    {
+        self.mapping.as_deref()
+    }
+
+    pub fn with_io_error(mut self, io_error: Rc<Cell<c_uint>>) -> Container
+    {
+        self.io_error = Some(io_error);
+        self
+    }
+
+    //Last user defined error code returned by a ContainerIo callback, ERR_NONE if there is none
+    pub fn io_error(&self) -> c_uint
+    {
+        self.io_error.as_ref().map(|v| v.get()).unwrap_or(ERR_NONE)
+    }
+
+    pub fn with_io_stats(mut self, io_stats: Rc<Cell<IoStats>>) -> Container
+    {
+        self.io_stats = Some(io_stats);
+        self
+    }
+
+    pub fn io_stats(&self) -> IoStats
+    {
+        self.io_stats.as_ref().map(|v| v.get()).unwrap_or_default()
+    }
+
+    pub fn is_read_only(&self) -> bool
+    {
+        self.read_only
+    }
+
+    pub fn check_writable(&self) -> Result<(), c_uint>
+    {
+        match self.read_only {
+            false => Ok(()),
+            true => Err(LastError::new(ERR_READ_ONLY, "container is read-only").set())
+        }
+    }
+
+    //Checks that the handle refers to a section which currently exists in this container
+    pub fn handle(&self, raw: Handle) -> Result<bpx::core::Handle, c_uint>
+    {
+        match self.handles.get(&raw) {
+            Some(v) => Ok(*v),
+            None => Err(LastError::new(ERR_INVALID_HANDLE, format!("invalid section handle ({})", raw)).set())
+        }
+    }
+
+    //Handle to give to C for a section of this container
+    pub fn export_handle(&self, handle: bpx::core::Handle) -> Handle
+    {
+        //Every section is registered when the container is built or when the section is created
+        self.exported[&handle.into_raw()]
+    }
+
+    //Sections must only be created through this function so that they get a handle usable from C
+    pub fn create_section(&mut self, builder: SectionHeaderBuilder) -> bpx::core::Handle
+    {
+        let handle = self.sections_mut().create(builder);
+        self.register(handle);
+        self.mark_modified(handle);
+        handle
+    }
+
+    //SAFETY: the section borrows this container, which therefore must not be mutated nor
+    // dropped until the section is closed (see check_no_open_sections).
+    pub unsafe fn open_section(&self, handle: bpx::core::Handle, data: RefMut<'_, bpx::core::AutoSectionData>) -> Section
+    {
+        self.open_sections.set(self.open_sections.get() + 1);
+        Section {
+            data: std::mem::transmute::<_, RefMut<'static, bpx::core::AutoSectionData>>(data),
+            read_only: self.read_only,
+            open_sections: self.open_sections.clone(),
+            handle: handle.into_raw(),
+            modified: self.modified.clone()
+        }
+    }
+
+    //Mirrors the borrow rules of the Rust API: a container cannot be mutated or closed while sections are open
+    pub fn check_no_open_sections(&self) -> Result<(), c_uint>
+    {
+        match self.open_sections.get() {
+            0 => Ok(()),
+            n => Err(LastError::new(ERR_OPEN_SECTION_IN_USE, format!("{} section(s) are still open", n)).set())
+        }
+    }
+
+    //Borrows the backend, which BPX must not be using at the same time
+    pub fn backend(&self) -> RefMut<'_, ContainerWrapper>
+    {
+        self.backend.borrow_mut()
+    }
+
+    fn mark_modified(&self, handle: bpx::core::Handle)
+    {
+        self.modified.borrow_mut().insert(handle.into_raw());
+    }
+
+    pub fn is_modified(&self, handle: bpx::core::Handle) -> bool
+    {
+        self.modified.borrow().contains(&handle.into_raw())
+    }
+
+    pub fn raw_section(&self, handle: bpx::core::Handle) -> Option<RawSection>
+    {
+        self.raw_sections.borrow().get(&handle.into_raw()).copied()
+    }
+
+    pub fn set_raw_section(&self, handle: bpx::core::Handle, section: RawSection)
+    {
+        self.raw_sections.borrow_mut().insert(handle.into_raw(), section);
+    }
+
+    pub fn raw_sections(&self) -> Vec<(bpx::core::Handle, RawSection)>
+    {
+        self.sections().iter().filter_map(|v| self.raw_section(v).map(|r| (v, r))).collect()
+    }
+
+    //Header of the section as it is (or will be) written by save, only the pointer of raw sections comes from BPX
+    pub fn section_header(&self, handle: bpx::core::Handle) -> bpx::core::header::SectionHeader
+    {
+        let header = *self.sections().header(handle);
+        match self.raw_section(handle) {
+            Some(v) => bpx::core::header::SectionHeader {
+                pointer: header.pointer,
+                ..*v.header()
+            },
+            None => header
+        }
+    }
+
+    //Must be called before handing the content of a section to BPX or to the user
+    pub fn decode_raw(&self, handle: bpx::core::Handle) -> Result<(), c_uint>
+    {
+        raw_section::decode(self, handle)
+    }
+
+    pub fn remove_section(&mut self, handle: bpx::core::Handle)
+    {
+        if let Some(raw) = self.exported.remove(&handle.into_raw()) {
+            self.handles.remove(&raw);
+        }
+        self.modified.borrow_mut().remove(&handle.into_raw());
+        self.raw_sections.borrow_mut().remove(&handle.into_raw());
+        self.sections_mut().remove(handle);
+    }
+
+    //Removes old, the handle given to C for old then designates new
+    pub fn replace_section(&mut self, old: bpx::core::Handle, new: bpx::core::Handle)
+    {
+        let raw = self.export_handle(old);
+        let new_raw = self.export_handle(new);
+        self.remove_section(old);
+        self.handles.remove(&new_raw);
+        self.handles.insert(raw, new);
+        self.exported.insert(new.into_raw(), raw);
+    }
+
+    //Reopens the container from its backend so that BPX reads the sections as they are stored, the handles given to C
+    // keep designating the same sections as BPX reads them back in the order it wrote them
+    fn reload(&mut self) -> Result<(), c_uint>
+    {
+        let inner = bpx::core::Container::open(self.backend.clone()).map_err(|e| e.cerr_code())?;
+        let handles: Vec<(Handle, bpx::core::Handle)> = self.sections().iter()
+            .zip(inner.sections().iter())
+            .map(|(old, new)| (self.export_handle(old), new))
+            .collect();
+        self.inner = inner;
+        self.handles.clear();
+        self.exported.clear();
+        for (raw, handle) in handles {
+            self.handles.insert(raw, handle);
+            self.exported.insert(handle.into_raw(), raw);
+        }
+        self.raw_sections.borrow_mut().clear();
+        Ok(())
+    }
+
+    //Saves through BPX, restores the headers of the raw sections which BPX wrote as uncompressed data, then reloads the
+    // container so that BPX sees them compressed
+    pub fn save_changes(&mut self) -> Result<(), c_uint>
+    {
+        raw_section::encode_decoded(self)?;
+        self.inner.save().map_err(|e| e.cerr_code())?;
+        let patched = raw_section::patch_headers(self)?;
+        //BPX never flushes its backend, pending buffered writes would otherwise only be written (or lost) on close
+        if let ContainerWrapper::Buffered(v) = &mut *self.backend() {
+            v.flush().map_err(|e| bpx::core::error::Error::Io(e).cerr_code())?;
+        }
+        if patched {
+            self.reload()?;
+        }
+        self.modified.borrow_mut().clear();
+        self.saved = true;
+        Ok(())
+    }
+
+    //Returns None if the backend is still borrowed elsewhere, which cannot happen once the container is consumed
+    pub fn into_backend(self) -> Option<ContainerWrapper>
+    {
+        drop(self.inner);
+        self.backend.into_inner()
+    }
+}
+
+impl Deref for Container
+{
+    type Target = bpx::core::Container<SharedWrapper>;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.inner
+    }
+}
+
+impl DerefMut for Container
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+        &mut self.inner
+    }
+}
+
+pub struct Section
+{
+    data: RefMut<'static, bpx::core::AutoSectionData>,
+    read_only: bool,
+    open_sections: Rc<Cell<usize>>,
+    handle: u32,
+    modified: Rc<RefCell<HashSet<u32>>>
+}
+
+impl Section
+{
+    //Every write goes through this check, which also marks the section as modified (see Container::is_modified)
+    pub fn check_writable(&self) -> Result<(), c_uint>
+    {
+        match self.read_only {
+            false => {
+                self.modified.borrow_mut().insert(self.handle);
+                Ok(())
+            },
+            true => Err(LastError::new(ERR_READ_ONLY, "section belongs to a read-only container").set())
+        }
+    }
+}
+
+impl Drop for Section
+{
+    fn drop(&mut self)
+    {
+        self.open_sections.set(self.open_sections.get() - 1);
+    }
+}
+
+impl Deref for Section
+{
+    type Target = bpx::core::AutoSectionData;
+
+    fn deref(&self) -> &Self::Target
+    {
+        &self.data
+    }
+}
+
+impl DerefMut for Section
+{
+    fn deref_mut(&mut self) -> &mut Self::Target
+    {
+        &mut self.data
+    }
+}
+
+#[repr(C)]
+pub struct Buffer
+{
+    pub data: *mut u8,
+    pub size: usize
+}
+
+impl Buffer
+{
+    pub fn new(data: Vec<u8>) -> Buffer
+    {
+        let data = Box::into_raw(data.into_boxed_slice());
+        Buffer {
+            size: data.len(),
+            data: data as *mut u8
+        }
+    }
+
+    pub unsafe fn free(&mut self)
+    {
+        if self.data.is_null() {
+            return;
+        }
+        let host = Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.data, self.size));
+        drop(host); //Force deallocate buffer
+        self.data = std::ptr::null_mut(); //Reset user pointer
+        self.size = 0;
+    }
+}
 
 #[repr(C)]
 pub struct SectionHeader
@@ -26,14 +26,32 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use std::os::raw::c_char;
-use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint};
+use crate::error_codes::ERR_NONE;
+use std::ffi::{CStr, CString};
 use crate::ffi_helper::export;
 use crate::error_codes::unwrap_or_err;
+use crate::types::Buffer;
 
 export! {
     fn bpx_hash(str: *const c_char) -> u64 {
         let str = unwrap_or_err!(CStr::from_ptr(str).to_str().map_err(|_| 0));
         bpx::utils::hash(str)
     }
+
+    fn bpx_buffer_free(buffer: *mut Buffer) -> c_uint
+    {
+        (*buffer).free();
+        ERR_NONE
+    }
+
+    fn bpx_string_free(str: *mut *mut c_char) -> c_uint
+    {
+        if !(*str).is_null() {
+            let host = CString::from_raw(*str);
+            drop(host); //Force deallocate string
+            *str = std::ptr::null_mut(); //Reset user pointer
+        }
+        ERR_NONE
+    }
 }
@@ -0,0 +1,333 @@
+// Copyright (c) 2022, BlockProject 3D
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//     * Redistributions in binary form must reproduce the above copyright notice,
+//       this list of conditions and the following disclaimer in the documentation
+//       and/or other materials provided with the distribution.
+//     * Neither the name of BlockProject 3D nor the names of its contributors
+//       may be used to endorse or promote products derived from this software
+//       without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT OWNER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::os::raw::c_uint;
+use crate::container::{copy_options, copy_section};
+use crate::container_wrapper::ContainerWrapper;
+use crate::error_codes::{ERR_CONTAINER_CORRUPTED, ERR_CORE_CHKSUM, ERR_NONE, ERR_SECTION_OUT_OF_BOUNDS, ERR_SECTION_SIZE_MISMATCH};
+use crate::error_codes::unwrap_or_err;
+use crate::io_wrapper::{ContainerIo, IoWrapper};
+use crate::open::{create_container, open_container, ContainerOptions};
+use crate::ffi_helper::export;
+use crate::ffi_helper::export_object;
+use crate::ffi_helper::OutCell;
+use crate::last_error::LastError;
+use crate::raw_section;
+use crate::types::{Container, Handle};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SectionFailure
+{
+    pub handle: Handle,
+    pub code: c_uint
+}
+
+#[repr(C)]
+pub struct VerifyReport
+{
+    pub main_header: c_uint,
+    pub failures: *mut SectionFailure,
+    pub failure_count: usize
+}
+
+impl VerifyReport
+{
+    pub fn new(main_header: c_uint, failures: Vec<SectionFailure>) -> VerifyReport
+    {
+        let failures = Box::into_raw(failures.into_boxed_slice());
+        VerifyReport {
+            main_header,
+            failure_count: failures.len(),
+            failures: failures as *mut SectionFailure
+        }
+    }
+
+    pub fn is_ok(&self) -> bool
+    {
+        self.main_header == ERR_NONE && self.failure_count == 0
+    }
+
+    pub unsafe fn free(&mut self)
+    {
+        if self.failures.is_null() {
+            return;
+        }
+        let host = Box::from_raw(std::ptr::slice_from_raw_parts_mut(self.failures, self.failure_count));
+        drop(host); //Force deallocate failure list
+        self.failures = std::ptr::null_mut(); //Reset user pointer
+        self.failure_count = 0;
+    }
+}
+
+//Recomputes the checksum of the main header and all section headers as stored in the backend by the last save, so
+// that unsaved changes are not reported as a mismatch. A container which was never saved has nothing to verify.
+pub fn verify_main_header(container: &Container) -> c_uint
+{
+    if !container.is_saved() {
+        return ERR_NONE;
+    }
+    let (main_header, headers) = match raw_section::read_headers(container) {
+        Ok(v) => v,
+        Err(e) => return e
+    };
+    let expected = u32::from_le_bytes([main_header[4], main_header[5], main_header[6], main_header[7]]);
+    let chksum = raw_section::main_header_checksum(&main_header, &headers);
+    if chksum != expected {
+        return LastError::new(ERR_CORE_CHKSUM, format!("main header checksum mismatch: expected 0x{:08X}, got 0x{:08X}", expected, chksum))
+            .checksum(expected, chksum).set();
+    }
+    ERR_NONE
+}
+
+//Decodes the section as stored in the backend apart from the container (see raw_section::decode_payload), so that
+// verifying does not leave the section loaded
+pub fn verify_section(container: &Container, handle: bpx::core::Handle) -> c_uint
+{
+    let header = *container.sections().header(handle);
+    let file_size = container.get_main_header().file_size;
+    if header.pointer.checked_add(header.csize as u64).map_or(true, |end| end > file_size) {
+        return LastError::new(ERR_SECTION_OUT_OF_BOUNDS, format!("section {} is out of bounds ({} + {} > {})",
+            container.export_handle(handle), header.pointer, header.csize, file_size)).set();
+    }
+    let content = match raw_section::read_stored(container, &header)
+        .and_then(|payload| raw_section::decode_payload(&header, &payload, container.get_main_header().version)) {
+        Ok(v) => v,
+        Err(e) => return e
+    };
+    if content.len() != header.size as usize {
+        return LastError::new(ERR_SECTION_SIZE_MISMATCH, format!("section {} size mismatch: expected {}, got {}",
+            container.export_handle(handle), header.size, content.len())).set();
+    }
+    ERR_NONE
+}
+
+//Sections with unsaved changes are skipped: their stored copy is about to be replaced by the next save
+pub fn verify(container: &Container) -> VerifyReport
+{
+    let main_header = verify_main_header(container);
+    let failures = container.sections().iter().filter(|v| !container.is_modified(*v)).filter_map(|v| {
+        match verify_section(container, v) {
+            ERR_NONE => None,
+            code => Some(SectionFailure {
+                handle: container.export_handle(v),
+                code
+            })
+        }
+    }).collect();
+    VerifyReport::new(main_header, failures)
+}
+
+//Copies every valid section of src into dst, the report lists the sections which were left out
+pub unsafe fn salvage(src: &Container, dst: &mut Container) -> VerifyReport
+{
+    let mut failures = Vec::new();
+    for handle in src.sections().iter() {
+        let mut code = verify_section(src, handle);
+        if code == ERR_NONE {
+            let options = copy_options(src.sections().header(handle));
+            code = copy_section(src, handle, dst, &options).map(|_| ERR_NONE).unwrap_or_else(|e| e);
+        }
+        if code != ERR_NONE {
+            failures.push(SectionFailure {
+                handle: src.export_handle(handle),
+                code
+            });
+        }
+    }
+    VerifyReport::new(verify_main_header(src), failures)
+}
+
+export_object! {
+    Container {
+        fn bpx_container_verify(this, report: OutCell<VerifyReport>) -> c_uint {
+            let res = verify(this);
+            let code = if res.is_ok() {
+                ERR_NONE
+            } else {
+                LastError::new(ERR_CONTAINER_CORRUPTED, format!("container is corrupted (main header: 0x{:X}, {} damaged section(s))",
+                    res.main_header, res.failure_count)).set()
+            };
+            report.set(res);
+            code
+        }
+    }
+}
+
+export! {
+    //Copies every valid section of src into a new container written to dst, the report lists the dropped sections
+    fn bpx_container_salvage(src: ContainerIo, dst: ContainerIo, report: OutCell<VerifyReport>) -> c_uint {
+        let src = unwrap_or_err!(open_container(ContainerWrapper::from(IoWrapper::new(src)), true));
+        let header = src.get_main_header();
+        let options = ContainerOptions {
+            ty: header.ty,
+            version: header.version,
+            type_ext: header.type_ext
+        };
+        let mut dst = create_container(ContainerWrapper::from(IoWrapper::new(dst)), &options);
+        report.set(salvage(&src, &mut dst));
+        unwrap_or_err!(dst.save_changes());
+        ERR_NONE
+    }
+
+    fn bpx_verify_report_free(report: *mut VerifyReport) -> c_uint {
+        (*report).free();
+        ERR_NONE
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::{Seek, SeekFrom, Write};
+    use crate::container::{CHECKSUM_CRC32, COMPRESSION_ZLIB};
+    use crate::error_codes::ERR_NONE;
+    use crate::test_utils;
+    use super::*;
+
+    fn failures(report: &VerifyReport) -> Vec<Handle>
+    {
+        unsafe { std::slice::from_raw_parts(report.failures, report.failure_count) }.iter().map(|v| v.handle).collect()
+    }
+
+    //A saved container with a zlib section and a crc32 section, the payload of the second one is damaged
+    fn corrupted() -> (Vec<u8>, Vec<u8>)
+    {
+        let mut container = test_utils::create();
+        let first = test_utils::content(4096, 1);
+        let second = test_utils::content(512, 2);
+        test_utils::add_section(&mut container, COMPRESSION_ZLIB, &first);
+        test_utils::add_section(&mut container, CHECKSUM_CRC32, &second);
+        let container = test_utils::reopen(container);
+        let handle = container.sections().find_by_index(1).unwrap();
+        let pointer = container.sections().header(handle).pointer as usize;
+        let mut data = test_utils::into_bytes(container);
+        data[pointer + 10] ^= 0xFF;
+        (data, first)
+    }
+
+    #[test]
+    fn saved_container()
+    {
+        let mut container = test_utils::create();
+        test_utils::add_section(&mut container, COMPRESSION_ZLIB, &test_utils::content(4096, 1));
+        test_utils::add_section(&mut container, CHECKSUM_CRC32, &test_utils::content(512, 2));
+        let container = test_utils::reopen(container);
+        let mut report = verify(&container);
+        assert!(report.is_ok());
+        unsafe { report.free() };
+        //Verifying does not load the sections
+        for handle in container.sections().iter() {
+            assert!(container.sections().open(handle).is_err());
+        }
+    }
+
+    #[test]
+    fn never_saved()
+    {
+        let mut container = test_utils::create();
+        assert_eq!(verify_main_header(&container), ERR_NONE);
+        test_utils::add_section(&mut container, COMPRESSION_ZLIB, &test_utils::content(4096, 1));
+        let mut report = verify(&container);
+        assert!(report.is_ok());
+        unsafe { report.free() };
+    }
+
+    #[test]
+    fn corrupted_main_header()
+    {
+        let mut container = test_utils::create();
+        test_utils::add_section(&mut container, 0, &test_utils::content(64, 1));
+        let container = test_utils::reopen(container);
+        assert_eq!(verify_main_header(&container), ERR_NONE);
+        {
+            let mut backend = container.backend();
+            backend.seek(SeekFrom::Start(24)).unwrap();
+            backend.write_all(&[0xFF]).unwrap();
+        }
+        assert_eq!(verify_main_header(&container), ERR_CORE_CHKSUM);
+    }
+
+    #[test]
+    fn corrupted_section()
+    {
+        let (data, _) = corrupted();
+        let container = test_utils::open(data);
+        let mut report = verify(&container);
+        assert_eq!(report.main_header, ERR_NONE);
+        let handle = container.sections().find_by_index(1).unwrap();
+        assert_eq!(failures(&report), vec![container.export_handle(handle)]);
+        assert_ne!(unsafe { (*report.failures).code }, ERR_NONE);
+        unsafe { report.free() };
+    }
+
+    #[test]
+    fn modified_sections_are_skipped()
+    {
+        let mut container = test_utils::create();
+        test_utils::add_section(&mut container, CHECKSUM_CRC32, &test_utils::content(512, 2));
+        let container = test_utils::reopen(container);
+        let handle = container.sections().find_by_index(0).unwrap();
+        let data = container.sections().load(handle).unwrap();
+        {
+            let mut backend = container.backend();
+            backend.seek(SeekFrom::Start(container.sections().header(handle).pointer + 10)).unwrap();
+            backend.write_all(&[0xFF]).unwrap();
+        }
+        let mut report = verify(&container);
+        assert_eq!(report.failure_count, 1);
+        unsafe { report.free() };
+        //The stored copy is about to be replaced once the section is written to
+        let section = unsafe { container.open_section(handle, data) };
+        section.check_writable().unwrap();
+        drop(section);
+        let mut report = verify(&container);
+        assert!(report.is_ok());
+        unsafe { report.free() };
+    }
+
+    #[test]
+    fn salvage_drops_damaged_sections()
+    {
+        let (data, first) = corrupted();
+        let src = test_utils::open(data);
+        let mut dst = test_utils::create();
+        let mut report = unsafe { salvage(&src, &mut dst) };
+        let damaged = src.sections().find_by_index(1).unwrap();
+        assert_eq!(failures(&report), vec![src.export_handle(damaged)]);
+        unsafe { report.free() };
+        let dst = test_utils::reopen(dst);
+        assert_eq!(dst.sections().len(), 1);
+        let handle = dst.sections().find_by_index(0).unwrap();
+        assert_eq!(dst.sections().header(handle).flags & COMPRESSION_ZLIB, COMPRESSION_ZLIB);
+        assert_eq!(test_utils::read(&dst, handle), first);
+        let mut report = verify(&dst);
+        assert!(report.is_ok());
+        unsafe { report.free() };
+    }
+}
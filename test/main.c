@@ -1,11 +1,19 @@
 #include <bpx/open.h>
+#include <bpx/open_memory.h>
 #include <bpx/container.h>
 #include <bpx/section.h>
+#include <bpx/sd.h>
+#include <bpx/verify.h>
+#include <bpx/last_error.h>
+#include <bpx/utils.h>
 #include <bpx/error_codes.h>
 
 #include <stdio.h>
+#include <string.h>
 #include <assert.h>
 
+#define CONTENT_SIZE 4096
+
 static void print_main_header(bpx_container_t container)
 {
     bpx_main_header_t header;
@@ -18,10 +26,145 @@ static void print_main_header(bpx_container_t container)
     printf("-- END --\n");
 }
 
+static void fill_content(bpx_u8_t *content)
+{
+    for (int i = 0; i != CONTENT_SIZE; ++i)
+        content[i] = (bpx_u8_t)(i % 13);
+}
+
+static bpx_handle_t create_section(bpx_container_t container, bpx_u8_t flags, const bpx_u8_t *data, bpx_size_t size)
+{
+    bpx_section_options_t options = { size, 1, flags | BPX_COMPRESSION_THRESHOLD, 0 };
+    bpx_handle_t handle;
+    bpx_section_t section;
+    bpx_size_t written;
+    assert(bpx_container_create_section(container, &options, &handle) == BPX_ERR_NONE);
+    assert(bpx_section_open(container, handle, &section) == BPX_ERR_NONE);
+    assert(bpx_section_write2(section, data, size, &written) == BPX_ERR_NONE);
+    assert(written == size);
+    assert(bpx_section_close(&section) == BPX_ERR_NONE);
+    return handle;
+}
+
+static void encode_object(bpx_container_t container)
+{
+    bpx_sd_value_t root = bpx_sd_value_new_object();
+    bpx_sd_value_t value = bpx_sd_value_new_u32(42);
+    bpx_sd_value_t name = bpx_sd_value_new_string("test");
+    bpx_section_options_t options = { 0, 2, 0, 0 };
+    bpx_handle_t handle;
+    bpx_section_t section;
+    assert(bpx_sd_object_set(root.data.as_object, "value", &value) == BPX_ERR_NONE);
+    assert(bpx_sd_object_set(root.data.as_object, "name", &name) == BPX_ERR_NONE);
+    assert(bpx_container_create_section(container, &options, &handle) == BPX_ERR_NONE);
+    assert(bpx_section_open(container, handle, &section) == BPX_ERR_NONE);
+    assert(bpx_sd_value_encode(section, &root) == BPX_ERR_NONE);
+    assert(bpx_section_close(&section) == BPX_ERR_NONE);
+    bpx_sd_value_free(&root);
+}
+
+static void check_object(bpx_container_t container, bpx_handle_t handle)
+{
+    bpx_section_t section;
+    bpx_sd_value_t root;
+    assert(bpx_section_load(container, handle, &section) == BPX_ERR_NONE);
+    assert(bpx_sd_value_decode_section(section, &root) == BPX_ERR_NONE);
+    assert(bpx_section_close(&section) == BPX_ERR_NONE);
+    assert(root.type == BPX_SD_VALUE_TYPE_OBJECT);
+    bpx_sd_value_t value = bpx_sd_object_get(root.data.as_object, "value");
+    assert(value.type == BPX_SD_VALUE_TYPE_UINT32 && value.data.as_u32 == 42);
+    bpx_sd_value_t name = bpx_sd_object_get(root.data.as_object, "name");
+    assert(name.type == BPX_SD_VALUE_TYPE_STRING && strcmp(name.data.as_string, "test") == 0);
+    bpx_sd_value_free(&root);
+}
+
+static void check_content(bpx_container_t container, bpx_handle_t handle, const bpx_u8_t *content)
+{
+    static bpx_u8_t buffer[CONTENT_SIZE];
+    bpx_section_t section;
+    bpx_size_t read;
+    assert(bpx_section_load(container, handle, &section) == BPX_ERR_NONE);
+    assert(bpx_section_size(section) == CONTENT_SIZE);
+    assert(bpx_section_read2(section, buffer, CONTENT_SIZE, &read) == BPX_ERR_NONE);
+    assert(bpx_section_close(&section) == BPX_ERR_NONE);
+    assert(read == CONTENT_SIZE && memcmp(buffer, content, CONTENT_SIZE) == 0);
+}
+
+//Creates a container in memory, saves it, then checks it once reopened from the saved buffer
+static void test_memory_container(void)
+{
+    static bpx_u8_t content[CONTENT_SIZE];
+    bpx_container_options_t header = { 'T', 2, { 0 } };
+    bpx_container_t container;
+    bpx_buffer_t buffer;
+    fill_content(content);
+    assert(bpx_container_create_memory(&header, &container) == BPX_ERR_NONE);
+    create_section(container, BPX_COMPRESSION_ZLIB, content, CONTENT_SIZE);
+    create_section(container, BPX_CHECKSUM_CRC32, content, CONTENT_SIZE);
+    encode_object(container);
+    assert(bpx_container_save(container) == BPX_ERR_NONE);
+    assert(bpx_container_into_buffer(&container, &buffer) == BPX_ERR_NONE);
+    assert(container == NULL);
+
+    assert(bpx_container_open_memory(buffer.data, buffer.size, &container) == BPX_ERR_NONE);
+    print_main_header(container);
+    assert(bpx_container_count_sections(container, BPX_SECTION_TYPE_ANY) == 3);
+    bpx_verify_report_t report;
+    assert(bpx_container_verify(container, &report) == BPX_ERR_NONE);
+    assert(report.main_header == BPX_ERR_NONE && report.failure_count == 0);
+    bpx_verify_report_free(&report);
+    //Handles are only valid for the container which gave them
+    bpx_handle_t zlib;
+    bpx_handle_t crc32;
+    assert(bpx_container_find_section_by_index(container, 0, &zlib));
+    assert(bpx_container_find_section_by_index(container, 1, &crc32));
+    check_content(container, zlib, content);
+    check_content(container, crc32, content);
+    bpx_handle_t object;
+    assert(bpx_container_find_section_by_type(container, 2, &object));
+    check_object(container, object);
+
+    //Compressed sections are copied without being decompressed and stay readable
+    bpx_container_t copy;
+    bpx_handle_t copied;
+    bpx_section_header_t section_header;
+    assert(bpx_container_create_memory(&header, &copy) == BPX_ERR_NONE);
+    assert(bpx_container_copy_section(container, zlib, copy, &copied) == BPX_ERR_NONE);
+    check_content(copy, copied, content);
+    assert(bpx_container_save(copy) == BPX_ERR_NONE);
+    assert(bpx_section_get_header(copy, copied, &section_header) == BPX_ERR_NONE);
+    assert(section_header.flags & BPX_COMPRESSION_ZLIB);
+    check_content(copy, copied, content);
+    assert(bpx_container_close(&copy) == BPX_ERR_NONE);
+
+    bpx_container_close(&container);
+    assert(container == NULL);
+    bpx_buffer_free(&buffer);
+}
+
+static void test_last_error(void)
+{
+    bpx_container_options_t header = { 'T', 2, { 0 } };
+    bpx_container_t container;
+    bpx_section_t section;
+    bpx_error_info_t info;
+    assert(bpx_container_create_memory(&header, &container) == BPX_ERR_NONE);
+    assert(bpx_section_open(container, 1234, &section) == BPX_ERR_INVALID_HANDLE);
+    assert(bpx_get_last_error(&info));
+    assert(info.code == BPX_ERR_INVALID_HANDLE && info.message != NULL);
+    assert(bpx_get_last_error_message() != NULL);
+    //The next call clears it
+    assert(bpx_container_count_sections(container, BPX_SECTION_TYPE_ANY) == 0);
+    assert(!bpx_get_last_error(&info));
+    assert(bpx_container_close(&container) == BPX_ERR_NONE);
+}
+
 int main(int ac, const char **av)
 {
+    test_memory_container();
+    test_last_error();
     if (ac != 2)
-        return 1;
+        return 0;
     bpx_container_t container;
     bpx_error_t err = bpx_container_open(av[1], &container);
     if (err != BPX_ERR_NONE)